@@ -0,0 +1,19 @@
+use super::{read, AcpiError, Sdt};
+
+// field offsets from the start of the table
+const CENTURY: usize = 108;
+
+/// The fixed ACPI description table, which describes the power management hardware.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    /// The index of the century register in the CMOS RTC, or `None` if there is none.
+    pub century_register: Option<u8>,
+}
+
+impl Fadt {
+    pub(super) fn parse(table: &Sdt) -> Result<Self, AcpiError> {
+        Ok(Self {
+            century_register: read::<u8>(table.bytes, CENTURY).filter(|&index| index != 0),
+        })
+    }
+}
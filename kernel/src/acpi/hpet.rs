@@ -0,0 +1,20 @@
+use x86_64::PhysAddr;
+
+use super::{AcpiError, Sdt, SDT_HEADER_SIZE};
+
+// the address field of the generic address structure at SDT_HEADER_SIZE + 4
+const BASE_ADDRESS: usize = SDT_HEADER_SIZE + 8;
+
+/// The HPET description table, which locates the high precision event timer.
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub base_address: PhysAddr,
+}
+
+impl Hpet {
+    pub(super) fn parse(table: &Sdt) -> Result<Self, AcpiError> {
+        Ok(Self {
+            base_address: PhysAddr::new(table.field(BASE_ADDRESS)?),
+        })
+    }
+}
@@ -0,0 +1,222 @@
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use super::{read, AcpiError, Sdt, SDT_HEADER_SIZE};
+
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+
+const ENTRY_LOCAL_APIC: u8 = 0;
+const ENTRY_IO_APIC: u8 = 1;
+const ENTRY_INTERRUPT_SOURCE_OVERRIDE: u8 = 2;
+const ENTRY_LOCAL_APIC_NMI: u8 = 4;
+const ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE: u8 = 5;
+const ENTRY_LOCAL_X2APIC: u8 = 9;
+const ENTRY_LOCAL_X2APIC_NMI: u8 = 0xa;
+
+const PROCESSOR_ENABLED: u32 = 1;
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// The multiple APIC description table, which lists the interrupt controllers and CPUs.
+#[derive(Debug, Clone)]
+pub struct Madt {
+    pub local_apic_address: PhysAddr,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub interrupt_source_overrides: Vec<InterruptSourceOverride>,
+    pub local_apic_nmis: Vec<LocalApicNmi>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    pub uid: u32,
+    pub local_apic_id: u32,
+    pub enabled: bool,
+    /// Whether a disabled processor can be brought online.
+    pub online_capable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: PhysAddr,
+    /// The first global system interrupt handled by the I/O APIC.
+    pub gsi_base: u32,
+}
+
+/// Describes a legacy ISA interrupt that is not connected to the I/O APIC input with the same
+/// number, or not with the default (active high, edge triggered) signaling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    pub irq: u8,
+    /// The global system interrupt the IRQ is connected to.
+    pub gsi: u32,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+/// A local APIC input connected to the non-maskable interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalApicNmi {
+    /// The processor whose local APIC is meant, or `None` for all of them.
+    pub processor_uid: Option<u32>,
+    /// The LINT pin, 0 or 1.
+    pub lint: u8,
+    pub active_low: bool,
+    pub level_triggered: bool,
+}
+
+/// Decodes the polarity and trigger mode of the MPS INTI flags. "Conforms to the bus" means
+/// active high and edge triggered for ISA.
+fn decode_inti_flags(flags: u16) -> (bool, bool) {
+    let active_low = flags & 0b11 == 0b11;
+    let level_triggered = (flags >> 2) & 0b11 == 0b11;
+    (active_low, level_triggered)
+}
+
+impl Madt {
+    pub(super) fn parse(table: &Sdt) -> Result<Self, AcpiError> {
+        let mut madt = Self {
+            local_apic_address: PhysAddr::new(table.field::<u32>(SDT_HEADER_SIZE)? as u64),
+            processors: Vec::new(),
+            io_apics: Vec::new(),
+            interrupt_source_overrides: Vec::new(),
+            local_apic_nmis: Vec::new(),
+        };
+
+        let truncated = || AcpiError::TruncatedTable(table.signature());
+        let mut entries = &table.bytes[ENTRIES_OFFSET..];
+
+        while let [entry_type, length, ..] = *entries {
+            if length < 2 || length as usize > entries.len() {
+                return Err(truncated());
+            }
+            let entry = &entries[..length as usize];
+            entries = &entries[length as usize..];
+
+            match entry_type {
+                ENTRY_LOCAL_APIC => {
+                    let flags = read::<u32>(entry, 4).ok_or_else(truncated)?;
+                    madt.processors.push(Processor {
+                        uid: entry[2] as u32,
+                        local_apic_id: entry[3] as u32,
+                        enabled: flags & PROCESSOR_ENABLED != 0,
+                        online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0,
+                    });
+                }
+                ENTRY_LOCAL_X2APIC => {
+                    let flags = read::<u32>(entry, 8).ok_or_else(truncated)?;
+                    madt.processors.push(Processor {
+                        uid: read(entry, 12).ok_or_else(truncated)?,
+                        local_apic_id: read(entry, 4).ok_or_else(truncated)?,
+                        enabled: flags & PROCESSOR_ENABLED != 0,
+                        online_capable: flags & PROCESSOR_ONLINE_CAPABLE != 0,
+                    });
+                }
+                ENTRY_IO_APIC => madt.io_apics.push(IoApicInfo {
+                    id: read(entry, 2).ok_or_else(truncated)?,
+                    address: PhysAddr::new(read::<u32>(entry, 4).ok_or_else(truncated)? as u64),
+                    gsi_base: read(entry, 8).ok_or_else(truncated)?,
+                }),
+                ENTRY_INTERRUPT_SOURCE_OVERRIDE => {
+                    let flags = read::<u16>(entry, 8).ok_or_else(truncated)?;
+                    let (active_low, level_triggered) = decode_inti_flags(flags);
+                    madt.interrupt_source_overrides
+                        .push(InterruptSourceOverride {
+                            irq: read(entry, 3).ok_or_else(truncated)?,
+                            gsi: read(entry, 4).ok_or_else(truncated)?,
+                            active_low,
+                            level_triggered,
+                        });
+                }
+                ENTRY_LOCAL_APIC_NMI => {
+                    let processor_uid = read::<u8>(entry, 2).ok_or_else(truncated)?;
+                    let flags = read::<u16>(entry, 3).ok_or_else(truncated)?;
+                    let (active_low, level_triggered) = decode_inti_flags(flags);
+                    madt.local_apic_nmis.push(LocalApicNmi {
+                        processor_uid: (processor_uid != 0xff).then_some(processor_uid as u32),
+                        lint: read(entry, 5).ok_or_else(truncated)?,
+                        active_low,
+                        level_triggered,
+                    });
+                }
+                ENTRY_LOCAL_X2APIC_NMI => {
+                    let flags = read::<u16>(entry, 2).ok_or_else(truncated)?;
+                    let processor_uid = read::<u32>(entry, 4).ok_or_else(truncated)?;
+                    let (active_low, level_triggered) = decode_inti_flags(flags);
+                    madt.local_apic_nmis.push(LocalApicNmi {
+                        processor_uid: (processor_uid != u32::MAX).then_some(processor_uid),
+                        lint: read(entry, 8).ok_or_else(truncated)?,
+                        active_low,
+                        level_triggered,
+                    });
+                }
+                ENTRY_LOCAL_APIC_ADDRESS_OVERRIDE => {
+                    let address = read::<u64>(entry, 4).ok_or_else(truncated)?;
+                    madt.local_apic_address = PhysAddr::new(address);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(madt)
+    }
+}
+
+#[test_case]
+fn madt_entries_are_parsed() {
+    use super::checksum;
+    use alloc::{boxed::Box, vec};
+
+    let mut bytes = vec![0; ENTRIES_OFFSET];
+    bytes[..4].copy_from_slice(b"APIC");
+    bytes[SDT_HEADER_SIZE..SDT_HEADER_SIZE + 4].copy_from_slice(&0xfee0_0000u32.to_le_bytes());
+    bytes.extend_from_slice(&[ENTRY_LOCAL_APIC, 8, 0, 1, 1, 0, 0, 0]);
+    bytes.extend_from_slice(&[ENTRY_IO_APIC, 12, 2, 0, 0, 0, 0xc0, 0xfe, 0, 0, 0, 0]);
+    bytes.extend_from_slice(&[
+        ENTRY_INTERRUPT_SOURCE_OVERRIDE,
+        10,
+        0,
+        9,
+        9,
+        0,
+        0,
+        0,
+        0xf,
+        0,
+    ]);
+
+    let length = bytes.len() as u32;
+    bytes[4..8].copy_from_slice(&length.to_le_bytes());
+    bytes[9] = 0u8.wrapping_sub(checksum(&bytes));
+
+    let table = Sdt::from_bytes(Box::leak(bytes.into_boxed_slice())).unwrap();
+    let madt = Madt::parse(&table).unwrap();
+
+    assert_eq!(madt.local_apic_address, PhysAddr::new(0xfee0_0000));
+    assert_eq!(
+        madt.processors,
+        [Processor {
+            uid: 0,
+            local_apic_id: 1,
+            enabled: true,
+            online_capable: false,
+        }]
+    );
+    assert_eq!(
+        madt.io_apics,
+        [IoApicInfo {
+            id: 2,
+            address: PhysAddr::new(0xfec0_0000),
+            gsi_base: 0,
+        }]
+    );
+    assert_eq!(
+        madt.interrupt_source_overrides,
+        [InterruptSourceOverride {
+            irq: 9,
+            gsi: 9,
+            active_low: true,
+            level_triggered: true,
+        }]
+    );
+}
@@ -0,0 +1,46 @@
+use alloc::vec::Vec;
+use x86_64::PhysAddr;
+
+use super::{read, AcpiError, Sdt, SDT_HEADER_SIZE};
+
+const ENTRIES_OFFSET: usize = SDT_HEADER_SIZE + 8;
+const ENTRY_SIZE: usize = 16;
+
+/// The PCI express memory mapped configuration table.
+#[derive(Debug, Clone)]
+pub struct Mcfg {
+    pub regions: Vec<PciConfigRegion>,
+}
+
+/// The memory mapped configuration space of a range of PCI buses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciConfigRegion {
+    /// The address of the configuration space of bus 0, even if `start_bus` is not 0.
+    pub base_address: PhysAddr,
+    pub segment_group: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl Mcfg {
+    pub(super) fn parse(table: &Sdt) -> Result<Self, AcpiError> {
+        let entries = table
+            .bytes
+            .get(ENTRIES_OFFSET..)
+            .ok_or(AcpiError::TruncatedTable(table.signature()))?;
+
+        let regions = entries
+            .as_chunks::<ENTRY_SIZE>()
+            .0
+            .iter()
+            .map(|entry| PciConfigRegion {
+                base_address: PhysAddr::new(read(entry, 0).unwrap()),
+                segment_group: read(entry, 8).unwrap(),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+            .collect();
+
+        Ok(Self { regions })
+    }
+}
@@ -0,0 +1,256 @@
+mod fadt;
+mod hpet;
+mod madt;
+mod mcfg;
+
+use conquer_once::spin::OnceCell;
+use core::{fmt, mem, slice, str};
+use x86_64::PhysAddr;
+
+use crate::memory;
+
+pub use fadt::Fadt;
+pub use hpet::Hpet;
+pub use madt::{InterruptSourceOverride, Madt};
+pub use mcfg::Mcfg;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// Size of the part of the RSDP covered by the checksum of ACPI 1.0.
+const RSDP_V1_SIZE: usize = 20;
+const RSDP_V2_SIZE: usize = 36;
+const SDT_HEADER_SIZE: usize = 36;
+
+static TABLES: OnceCell<AcpiTables> = OnceCell::uninit();
+
+/// The ACPI tables the kernel understands. Tables the firmware does not provide are `None`.
+#[derive(Debug)]
+pub struct AcpiTables {
+    pub revision: u8,
+    pub oem_id: [u8; 6],
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<Hpet>,
+    pub mcfg: Option<Mcfg>,
+}
+
+#[derive(Debug)]
+pub enum AcpiError {
+    InvalidRsdp,
+    InvalidSignature(Signature),
+    InvalidChecksum(Signature),
+    TruncatedTable(Signature),
+}
+
+/// The four character signature of a system description table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature(pub [u8; 4]);
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(str::from_utf8(&self.0).unwrap_or("????"))
+    }
+}
+
+impl fmt::Display for AcpiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidRsdp => write!(f, "invalid RSDP"),
+            Self::InvalidSignature(signature) => write!(f, "unexpected table {signature}"),
+            Self::InvalidChecksum(signature) => write!(f, "invalid checksum of table {signature}"),
+            Self::TruncatedTable(signature) => write!(f, "table {signature} is truncated"),
+        }
+    }
+}
+
+/// Returns the ACPI tables, or `None` if the firmware does not provide valid ones.
+pub(crate) fn tables() -> Option<&'static AcpiTables> {
+    TABLES.get()
+}
+
+/// Parses the tables reachable from the RSDP at `rsdp_address`.
+///
+/// ## Safety
+///
+/// `rsdp_address` must be the address of the RSDP provided by the firmware.
+#[deny(unsafe_op_in_unsafe_fn)]
+pub unsafe fn init(rsdp_address: PhysAddr) -> Result<(), AcpiError> {
+    let tables = unsafe { AcpiTables::parse(rsdp_address)? };
+
+    let madt = tables.madt.as_ref();
+    log::info!(
+        "ACPI {} tables from {}: {} CPUs, {} I/O APICs, FADT: {}, HPET: {:?}, {} PCI config regions",
+        tables.revision,
+        str::from_utf8(&tables.oem_id).unwrap_or("?").trim_end(),
+        madt.map_or(0, |madt| madt.processors.len()),
+        madt.map_or(0, |madt| madt.io_apics.len()),
+        tables.fadt.is_some(),
+        tables.hpet.map(|hpet| hpet.base_address),
+        tables.mcfg.as_ref().map_or(0, |mcfg| mcfg.regions.len()),
+    );
+
+    TABLES.init_once(|| tables);
+    Ok(())
+}
+
+impl AcpiTables {
+    #[deny(unsafe_op_in_unsafe_fn)]
+    unsafe fn parse(rsdp_address: PhysAddr) -> Result<Self, AcpiError> {
+        let rsdp = unsafe { physical_bytes(rsdp_address, RSDP_V1_SIZE) };
+        if &rsdp[..8] != RSDP_SIGNATURE || checksum(rsdp) != 0 {
+            return Err(AcpiError::InvalidRsdp);
+        }
+
+        let revision = rsdp[15];
+        let oem_id = rsdp[9..15].try_into().unwrap();
+
+        // ACPI 2.0 added the XSDT with 64 bit table addresses
+        let (root, entry_size) = if revision >= 2 {
+            let rsdp = unsafe { physical_bytes(rsdp_address, RSDP_V2_SIZE) };
+            let length = read::<u32>(rsdp, 20).unwrap() as usize;
+            if length < RSDP_V2_SIZE {
+                return Err(AcpiError::InvalidRsdp);
+            }
+
+            let rsdp = unsafe { physical_bytes(rsdp_address, length) };
+            if checksum(rsdp) != 0 {
+                return Err(AcpiError::InvalidRsdp);
+            }
+
+            let xsdt_address = read::<u64>(rsdp, 24).unwrap();
+            let xsdt = unsafe { Sdt::new(PhysAddr::new(xsdt_address))? };
+            (xsdt.expect(b"XSDT")?, mem::size_of::<u64>())
+        } else {
+            let rsdt_address = read::<u32>(rsdp, 16).unwrap();
+            let rsdt = unsafe { Sdt::new(PhysAddr::new(rsdt_address as u64))? };
+            (rsdt.expect(b"RSDT")?, mem::size_of::<u32>())
+        };
+
+        let mut tables = Self {
+            revision,
+            oem_id,
+            madt: None,
+            fadt: None,
+            hpet: None,
+            mcfg: None,
+        };
+
+        for entry in root.data().chunks_exact(entry_size) {
+            let address = match entry_size {
+                4 => read::<u32>(entry, 0).unwrap() as u64,
+                _ => read::<u64>(entry, 0).unwrap(),
+            };
+
+            if let Err(error) = unsafe { tables.add(PhysAddr::new(address)) } {
+                log::warn!("Skipping ACPI table at {address:#x}: {error}");
+            }
+        }
+
+        Ok(tables)
+    }
+
+    #[deny(unsafe_op_in_unsafe_fn)]
+    unsafe fn add(&mut self, address: PhysAddr) -> Result<(), AcpiError> {
+        let table = unsafe { Sdt::new(address)? };
+
+        match &table.signature().0 {
+            b"APIC" => self.madt = Some(Madt::parse(&table)?),
+            b"FACP" => self.fadt = Some(Fadt::parse(&table)?),
+            b"HPET" => self.hpet = Some(Hpet::parse(&table)?),
+            b"MCFG" => self.mcfg = Some(Mcfg::parse(&table)?),
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// A system description table with a valid header and checksum.
+struct Sdt {
+    bytes: &'static [u8],
+}
+
+impl Sdt {
+    /// ## Safety
+    ///
+    /// A system description table must be located at `address`.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    unsafe fn new(address: PhysAddr) -> Result<Self, AcpiError> {
+        let header = unsafe { physical_bytes(address, SDT_HEADER_SIZE) };
+        let length = read::<u32>(header, 4).unwrap() as usize;
+
+        Self::from_bytes(unsafe { physical_bytes(address, length.max(SDT_HEADER_SIZE)) })
+    }
+
+    fn from_bytes(bytes: &'static [u8]) -> Result<Self, AcpiError> {
+        let signature = Signature(bytes[..4].try_into().unwrap());
+        let length = read::<u32>(bytes, 4).unwrap() as usize;
+
+        if length < SDT_HEADER_SIZE || length > bytes.len() {
+            return Err(AcpiError::TruncatedTable(signature));
+        }
+
+        let bytes = &bytes[..length];
+        if checksum(bytes) != 0 {
+            return Err(AcpiError::InvalidChecksum(signature));
+        }
+
+        Ok(Self { bytes })
+    }
+
+    fn signature(&self) -> Signature {
+        Signature(self.bytes[..4].try_into().unwrap())
+    }
+
+    fn expect(self, signature: &[u8; 4]) -> Result<Self, AcpiError> {
+        if &self.signature().0 == signature {
+            Ok(self)
+        } else {
+            Err(AcpiError::InvalidSignature(self.signature()))
+        }
+    }
+
+    /// The table after its header.
+    fn data(&self) -> &'static [u8] {
+        &self.bytes[SDT_HEADER_SIZE..]
+    }
+
+    /// Reads the field at `offset` from the start of the table.
+    fn field<T: Field>(&self, offset: usize) -> Result<T, AcpiError> {
+        read(self.bytes, offset).ok_or(AcpiError::TruncatedTable(self.signature()))
+    }
+}
+
+/// An integer stored in little endian byte order in an ACPI table.
+trait Field: Sized {
+    fn read(bytes: &[u8], offset: usize) -> Option<Self>;
+}
+
+macro_rules! impl_field {
+    ($($type:ty),*) => {$(
+        impl Field for $type {
+            fn read(bytes: &[u8], offset: usize) -> Option<Self> {
+                let bytes = bytes.get(offset..offset + mem::size_of::<Self>())?;
+                Some(Self::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+
+impl_field!(u8, u16, u32, u64);
+
+fn read<T: Field>(bytes: &[u8], offset: usize) -> Option<T> {
+    T::read(bytes, offset)
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// ## Safety
+///
+/// The physical range must be memory that is not written to.
+#[deny(unsafe_op_in_unsafe_fn)]
+unsafe fn physical_bytes(address: PhysAddr, length: usize) -> &'static [u8] {
+    let ptr = memory::physical_to_virtual(address).as_ptr();
+    unsafe { slice::from_raw_parts(ptr, length) }
+}
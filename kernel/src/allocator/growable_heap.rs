@@ -0,0 +1,189 @@
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+use linked_list_allocator::Heap;
+use x86_64::{
+    structures::paging::{mapper::MapToError, PageSize, Size4KiB},
+    VirtAddr,
+};
+
+use super::{map_heap_pages, HEAP_MAX_SIZE};
+use crate::memory;
+
+/// The heap grows by at least this many bytes whenever it runs out of memory.
+const HEAP_GROWTH_STEP: usize = 64 * 1024;
+
+/// A heap that maps additional pages whenever an allocation does not fit, up to the maximum size
+/// given to [`super::init_heap`].
+pub struct GrowableHeap {
+    heap: Heap,
+    start: VirtAddr,
+    mapped_size: usize,
+    max_size: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub free: usize,
+    pub mapped: usize,
+    pub max_size: usize,
+}
+
+impl GrowableHeap {
+    pub const fn new() -> Self {
+        Self {
+            heap: Heap::empty(),
+            start: VirtAddr::zero(),
+            mapped_size: 0,
+            max_size: HEAP_MAX_SIZE,
+        }
+    }
+
+    /// Takes over `size` already mapped bytes at `start`. The heap grows into the following
+    /// `max_size - size` bytes.
+    ///
+    /// ## Safety
+    ///
+    /// The first `size` bytes must be mapped writable and the whole range must not be used for
+    /// anything else.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn init(&mut self, start: VirtAddr, size: usize, max_size: usize) {
+        unsafe { self.heap.init(start.as_mut_ptr(), size) };
+        self.start = start;
+        self.mapped_size = size;
+        self.max_size = max_size.max(size);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            free: self.heap.free(),
+            mapped: self.mapped_size,
+            max_size: self.max_size,
+        }
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        if let Ok(ptr) = self.heap.allocate_first_fit(layout) {
+            return Some(ptr);
+        }
+
+        let required = layout.size() + layout.align();
+        self.grow(required.max(HEAP_GROWTH_STEP)).ok()?;
+
+        self.heap.allocate_first_fit(layout).ok()
+    }
+
+    /// Returns the size of the largest block that can be allocated without growing the heap.
+    ///
+    /// `linked_list_allocator` does not expose its hole list, so this searches for the largest
+    /// size for which a trial allocation succeeds and releases every trial block again. It is
+    /// too slow for anything but an explicit request for statistics.
+    pub fn largest_free_block(&mut self) -> usize {
+        let align = core::mem::size_of::<usize>();
+        let (mut low, mut high) = (0, self.heap.free());
+
+        while low < high {
+            let size = (low + high).div_ceil(2);
+            let layout = Layout::from_size_align(size, align).unwrap();
+
+            match self.heap.allocate_first_fit(layout) {
+                Ok(ptr) => {
+                    unsafe { self.heap.deallocate(ptr, layout) };
+                    low = size;
+                }
+                Err(()) => high = size - 1,
+            }
+        }
+
+        low
+    }
+
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`Self::allocate`] with the same `layout`.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { self.heap.deallocate(ptr, layout) };
+    }
+
+    fn grow(&mut self, by: usize) -> Result<(), MapToError<Size4KiB>> {
+        let by = by.next_multiple_of(Size4KiB::SIZE as usize);
+
+        if self.mapped_size + by > self.max_size {
+            return Err(MapToError::FrameAllocationFailed);
+        }
+
+        map_heap_pages(
+            self.start + self.mapped_size,
+            by,
+            &mut *memory::mapper(),
+            &mut *memory::frame_allocator(),
+        )?;
+
+        unsafe { self.heap.extend(by) };
+        self.mapped_size += by;
+
+        Ok(())
+    }
+}
+
+#[test_case]
+fn failed_growth_maps_nothing() {
+    use crate::memory::RegionKind;
+    use x86_64::structures::paging::{mapper::Translate, FrameDeallocator, Mapper, Page};
+
+    let page_size = Size4KiB::SIZE as usize;
+    let start = memory::vmm()
+        .reserve(4 * page_size as u64, RegionKind::Heap)
+        .unwrap()
+        .start;
+    let page_start = |index: usize| start + (index * page_size) as u64;
+    let map = |index: usize| {
+        let mut mapper = memory::mapper();
+        map_heap_pages(
+            page_start(index),
+            page_size,
+            &mut *mapper,
+            &mut *memory::frame_allocator(),
+        )
+    };
+    let unmap = |index: usize| {
+        let page = Page::<Size4KiB>::containing_address(page_start(index));
+        let (frame, flush) = memory::mapper().unmap(page).unwrap();
+        flush.flush();
+        unsafe { memory::frame_allocator().deallocate_frame(frame) };
+    };
+
+    // the page tables stay after the pages are unmapped, so only the pages take frames below
+    for index in 0..4 {
+        map(index).unwrap();
+    }
+    for index in 1..4 {
+        unmap(index);
+    }
+
+    let mut heap = GrowableHeap::new();
+    unsafe { heap.init(start, page_size, 4 * page_size) };
+
+    // the third page is in the way, so the second one must not stay mapped
+    map(2).unwrap();
+    let free_frames = memory::frame_allocator().free_frames();
+    assert!(matches!(
+        heap.grow(2 * page_size),
+        Err(MapToError::PageAlreadyMapped(_))
+    ));
+    assert_eq!(heap.stats().mapped, page_size);
+    assert!(memory::mapper().translate_addr(page_start(1)).is_none());
+    assert_eq!(memory::frame_allocator().free_frames(), free_frames);
+
+    unmap(2);
+    heap.grow(3 * page_size).unwrap();
+    assert_eq!(heap.stats().mapped, 4 * page_size);
+    assert!(heap
+        .allocate(Layout::from_size_align(3 * page_size, 8).unwrap())
+        .is_some());
+
+    for index in 0..4 {
+        unmap(index);
+    }
+    memory::vmm().release(start).unwrap();
+}
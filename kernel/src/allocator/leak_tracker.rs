@@ -0,0 +1,180 @@
+use alloc::vec::Vec;
+use core::{
+    arch::asm,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use klib::interrupts::UninterruptibleMutex;
+
+/// Number of live allocations that can be recorded at the same time. Allocations beyond this
+/// limit are counted but not recorded.
+const MAX_TRACKED_ALLOCATIONS: usize = 1024;
+
+/// Number of return addresses recorded per allocation.
+pub const CALLER_DEPTH: usize = 4;
+
+/// Frames between `GlobalAlloc::alloc` and the code that requested the allocation
+/// (`__rg_alloc` and `__rust_alloc`).
+const SKIPPED_FRAMES: usize = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocationRecord {
+    pub address: usize,
+    pub size: usize,
+    /// Return addresses of the allocating call chain, innermost first. Unknown frames are zero.
+    pub callers: [usize; CALLER_DEPTH],
+}
+
+struct AllocationTable {
+    records: [Option<AllocationRecord>; MAX_TRACKED_ALLOCATIONS],
+    dropped: usize,
+}
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static TABLE: UninterruptibleMutex<AllocationTable> = UninterruptibleMutex::new(AllocationTable {
+    records: [None; MAX_TRACKED_ALLOCATIONS],
+    dropped: 0,
+});
+
+/// Records every allocation made until [`LeakTracker::finish`] is called and forgets it again
+/// once it is freed. Only one tracker should be active at a time.
+pub struct LeakTracker {
+    _private: (),
+}
+
+pub struct LeakReport {
+    /// Allocations made while tracking that were still alive when it finished.
+    pub leaks: Vec<AllocationRecord>,
+    /// Allocations that could not be recorded because the table was full.
+    pub dropped: usize,
+}
+
+impl LeakTracker {
+    pub fn start() -> Self {
+        let mut table = TABLE.lock();
+        table.records.fill(None);
+        table.dropped = 0;
+        TRACKING.store(true, Ordering::SeqCst);
+
+        Self { _private: () }
+    }
+
+    pub fn finish(self) -> LeakReport {
+        TRACKING.store(false, Ordering::SeqCst);
+
+        let count = TABLE.lock().records.iter().flatten().count();
+        let mut leaks = Vec::with_capacity(count);
+
+        let table = TABLE.lock();
+        leaks.extend(table.records.iter().flatten());
+
+        LeakReport {
+            leaks,
+            dropped: table.dropped,
+        }
+    }
+}
+
+impl Drop for LeakTracker {
+    fn drop(&mut self) {
+        TRACKING.store(false, Ordering::SeqCst);
+    }
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.leaks.is_empty() && self.dropped == 0
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} leaked allocations", self.leaks.len())?;
+
+        for leak in &self.leaks {
+            writeln!(
+                f,
+                "  {} bytes at {:#x}, allocated from {:x?}",
+                leak.size, leak.address, leak.callers
+            )?;
+        }
+
+        if self.dropped > 0 {
+            writeln!(f, "  {} allocations were not recorded", self.dropped)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Records an allocation if a [`LeakTracker`] is active.
+///
+/// Must be called directly from `GlobalAlloc::alloc` so the skipped frames line up.
+#[inline(always)]
+pub(super) fn record_allocation(address: usize, size: usize) {
+    if !TRACKING.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let record = AllocationRecord {
+        address,
+        size,
+        callers: callers(),
+    };
+
+    let mut table = TABLE.lock();
+    match table.records.iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => *slot = Some(record),
+        None => table.dropped += 1,
+    }
+}
+
+pub(super) fn record_deallocation(address: usize) {
+    if !TRACKING.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut table = TABLE.lock();
+    if let Some(slot) = table
+        .records
+        .iter_mut()
+        .find(|slot| slot.is_some_and(|record| record.address == address))
+    {
+        *slot = None;
+    }
+}
+
+/// Walks the frame pointer chain of the current stack.
+///
+/// The kernel is built with frame pointers, but precompiled parts of `core` and `alloc` may not
+/// be, so the walk stops as soon as a saved frame pointer does not look like a caller's frame.
+#[inline(always)]
+fn callers() -> [usize; CALLER_DEPTH] {
+    const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+    let mut callers = [0; CALLER_DEPTH];
+    let mut frame: usize;
+    unsafe { asm!("mov {}, rbp", out(reg) frame, options(nomem, nostack, preserves_flags)) };
+
+    for depth in 0..SKIPPED_FRAMES + CALLER_DEPTH {
+        if frame == 0 || frame % 8 != 0 {
+            break;
+        }
+
+        let (next_frame, return_address) = unsafe {
+            let frame = frame as *const usize;
+            (*frame, *frame.add(1))
+        };
+
+        if depth >= SKIPPED_FRAMES {
+            callers[depth - SKIPPED_FRAMES] = return_address;
+        }
+
+        if next_frame <= frame || next_frame - frame > MAX_FRAME_SIZE {
+            break;
+        }
+        frame = next_frame;
+    }
+
+    callers
+}
@@ -0,0 +1,282 @@
+mod growable_heap;
+#[cfg(test)]
+mod leak_tracker;
+mod slab;
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+use klib::interrupts::UninterruptibleMutex;
+use x86_64::{
+    structures::paging::{
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags,
+        Size4KiB,
+    },
+    VirtAddr,
+};
+
+use crate::memory::{self, RegionKind, VmmError};
+use slab::SlabAllocator;
+
+#[cfg(test)]
+pub use leak_tracker::LeakTracker;
+pub use slab::AllocatorCounters;
+
+pub struct Dummy;
+
+unsafe impl GlobalAlloc for Dummy {
+    unsafe fn alloc(&self, _layout: Layout) -> *mut u8 {
+        null_mut()
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        panic!("dealloc should be never called")
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Locked<SlabAllocator> = Locked::new(SlabAllocator::new());
+
+pub const HEAP_INITIAL_SIZE: usize = 100 * 1024; // 100 KiB
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // 64 MiB
+
+pub struct Locked<A> {
+    inner: UninterruptibleMutex<A>,
+}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner: UninterruptibleMutex::new(inner),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for Locked<SlabAllocator> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = match self.inner.lock().allocate(layout) {
+            Some(ptr) => ptr.as_ptr(),
+            None => return null_mut(),
+        };
+
+        #[cfg(test)]
+        leak_tracker::record_allocation(ptr as usize, layout.size());
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        #[cfg(test)]
+        leak_tracker::record_deallocation(ptr as usize);
+
+        unsafe {
+            self.inner
+                .lock()
+                .deallocate(NonNull::new_unchecked(ptr), layout)
+        };
+    }
+}
+
+/// Maps `size` bytes at `start` to newly allocated frames, or nothing if one of the pages cannot
+/// be mapped.
+fn map_heap_pages<A>(
+    start: VirtAddr,
+    size: usize,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut A,
+) -> Result<(), MapToError<Size4KiB>>
+where
+    A: FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>,
+{
+    let page_range = {
+        let end = start + size - 1u64;
+        let start_page = Page::containing_address(start);
+        let end_page = Page::containing_address(end);
+        Page::range_inclusive(start_page, end_page)
+    };
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    for page in page_range {
+        let result = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)
+            .and_then(|frame| {
+                unsafe { mapper.map_to(page, frame, flags, frame_allocator) }.inspect_err(|_| {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                })
+            });
+
+        match result {
+            Ok(flush) => flush.flush(),
+            Err(error) => {
+                // the pages mapped so far would block every later attempt to map the range
+                for page in Page::range(page_range.start, page) {
+                    let (frame, flush) = mapper
+                        .unmap(page)
+                        .expect("failed to unmap a heap page that was just mapped");
+                    flush.flush();
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                }
+                return Err(error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reserves `max_size` bytes of address space for the heap, maps the beginning of it and lets
+/// the heap grow on demand.
+pub fn init_heap(max_size: usize) -> Result<(), VmmError> {
+    let max_size = max_size.max(HEAP_INITIAL_SIZE);
+    let heap_start = memory::vmm()
+        .reserve(max_size as u64, RegionKind::Heap)?
+        .start;
+
+    map_heap_pages(
+        heap_start,
+        HEAP_INITIAL_SIZE,
+        &mut *memory::mapper(),
+        &mut *memory::frame_allocator(),
+    )?;
+
+    unsafe {
+        ALLOCATOR
+            .inner
+            .lock()
+            .large_heap()
+            .init(heap_start, HEAP_INITIAL_SIZE, max_size)
+    };
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStatistics {
+    /// Bytes requested by live allocations.
+    pub used_bytes: usize,
+    /// Bytes that can be allocated without mapping more memory.
+    pub free_bytes: usize,
+    pub peak_bytes: usize,
+    pub mapped_bytes: usize,
+    pub max_bytes: usize,
+    pub live_allocations: usize,
+    pub total_allocations: usize,
+}
+
+pub fn statistics() -> HeapStatistics {
+    let mut allocator = ALLOCATOR.inner.lock();
+    let counters = allocator.counters();
+    let free_block_bytes = allocator.free_block_bytes();
+    let heap = allocator.large_heap().stats();
+
+    HeapStatistics {
+        used_bytes: counters.bytes_in_use,
+        free_bytes: heap.free + free_block_bytes,
+        peak_bytes: counters.peak_bytes_in_use,
+        mapped_bytes: heap.mapped,
+        max_bytes: heap.max_size,
+        live_allocations: counters.allocations - counters.deallocations,
+        total_allocations: counters.allocations,
+    }
+}
+
+/// Returns the size of the largest block that can be allocated without mapping more memory.
+/// This takes several trial allocations of the heap, which is locked meanwhile, so it is not part
+/// of [`statistics`].
+pub fn largest_free_block() -> usize {
+    ALLOCATOR.inner.lock().largest_free_block()
+}
+
+pub fn allocator_counters() -> AllocatorCounters {
+    ALLOCATOR.inner.lock().counters()
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    let stats = statistics();
+
+    log::error!(
+        "Heap exhausted: {} bytes used (peak {}), {} bytes free",
+        stats.used_bytes,
+        stats.peak_bytes,
+        stats.free_bytes
+    );
+    log::error!(
+        "{} of at most {} bytes mapped, {} of {} allocations alive",
+        stats.mapped_bytes,
+        stats.max_bytes,
+        stats.live_allocations,
+        stats.total_allocations
+    );
+
+    let counters = allocator_counters();
+    log::error!(
+        "Size class hits: {:?}, large allocations: {}",
+        counters.class_hits,
+        counters.large_allocations
+    );
+
+    panic!("allocation error: {layout:?}");
+}
+
+#[test_case]
+fn heap_grows_beyond_initial_size() {
+    use alloc::vec::Vec;
+
+    let len = 2 * HEAP_INITIAL_SIZE;
+    let mut vec = Vec::with_capacity(len);
+    vec.resize(len, 0xa5u8);
+
+    assert!(statistics().mapped_bytes > HEAP_INITIAL_SIZE);
+    assert!(vec.iter().all(|&byte| byte == 0xa5));
+}
+
+#[test_case]
+fn freed_memory_is_reused() {
+    use alloc::boxed::Box;
+
+    let mapped = statistics().mapped_bytes;
+
+    for i in 0..HEAP_INITIAL_SIZE {
+        let value = Box::new(i);
+        assert_eq!(*value, i);
+    }
+
+    assert_eq!(statistics().mapped_bytes, mapped);
+}
+
+#[test_case]
+fn small_allocations_use_size_classes() {
+    use alloc::boxed::Box;
+
+    let before = allocator_counters();
+    let value = Box::new(0u64);
+    let after = allocator_counters();
+
+    assert_eq!(after.class_hits[0], before.class_hits[0] + 1);
+    assert_eq!(after.large_allocations, before.large_allocations);
+    assert_eq!(after.bytes_in_use, before.bytes_in_use + 8);
+
+    drop(value);
+    assert_eq!(allocator_counters().bytes_in_use, before.bytes_in_use);
+}
+
+#[test_case]
+fn tracker_reports_leaks() {
+    use alloc::{boxed::Box, vec::Vec};
+
+    let tracker = LeakTracker::start();
+    let freed = Vec::<u8>::with_capacity(100);
+    let leaked = Box::new([0u8; 300]);
+    drop(freed);
+    let report = tracker.finish();
+
+    assert_eq!(report.leaks.len(), 1);
+    assert_eq!(report.leaks[0].address, &*leaked as *const _ as usize);
+    assert_eq!(report.leaks[0].size, 300);
+
+    let tracker = LeakTracker::start();
+    drop(leaked);
+    let report = tracker.finish();
+    assert!(report.is_empty(), "{report}");
+}
@@ -0,0 +1,159 @@
+use alloc::alloc::Layout;
+use core::{mem, ptr::NonNull};
+
+use super::growable_heap::GrowableHeap;
+
+/// Block sizes of the size classes. Every block is aligned to its own size, so a class can
+/// serve any layout whose size and alignment are both at most the block size.
+pub const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Size of the chunks taken from the large-object heap to refill an empty size class.
+const SLAB_SIZE: usize = 16 * 1024;
+
+struct FreeBlock {
+    next: Option<&'static mut FreeBlock>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocatorCounters {
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub bytes_in_use: usize,
+    pub peak_bytes_in_use: usize,
+    pub class_hits: [usize; SIZE_CLASSES.len()],
+    pub large_allocations: usize,
+}
+
+/// Serves small allocations from per-size-class free lists that are refilled in slabs, and
+/// everything else from a [`GrowableHeap`].
+pub struct SlabAllocator {
+    free_lists: [Option<&'static mut FreeBlock>; SIZE_CLASSES.len()],
+    large: GrowableHeap,
+    free_block_bytes: usize,
+    counters: AllocatorCounters,
+}
+
+fn size_class(layout: &Layout) -> Option<usize> {
+    let required = layout.size().max(layout.align());
+    SIZE_CLASSES.iter().position(|&size| size >= required)
+}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut FreeBlock> = None;
+
+        Self {
+            free_lists: [EMPTY; SIZE_CLASSES.len()],
+            large: GrowableHeap::new(),
+            free_block_bytes: 0,
+            counters: AllocatorCounters {
+                allocations: 0,
+                deallocations: 0,
+                bytes_in_use: 0,
+                peak_bytes_in_use: 0,
+                class_hits: [0; SIZE_CLASSES.len()],
+                large_allocations: 0,
+            },
+        }
+    }
+
+    pub fn large_heap(&mut self) -> &mut GrowableHeap {
+        &mut self.large
+    }
+
+    pub fn counters(&self) -> AllocatorCounters {
+        self.counters
+    }
+
+    /// Bytes held in the free lists of the size classes.
+    pub fn free_block_bytes(&self) -> usize {
+        self.free_block_bytes
+    }
+
+    pub fn largest_free_block(&mut self) -> usize {
+        let largest_block = (0..SIZE_CLASSES.len())
+            .rev()
+            .find(|&class| self.free_lists[class].is_some())
+            .map_or(0, |class| SIZE_CLASSES[class]);
+
+        largest_block.max(self.large.largest_free_block())
+    }
+
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let ptr = match size_class(&layout) {
+            Some(class) => {
+                let block = self.pop_block(class)?;
+                self.counters.class_hits[class] += 1;
+                block
+            }
+            None => {
+                let ptr = self.large.allocate(layout)?;
+                self.counters.large_allocations += 1;
+                ptr
+            }
+        };
+
+        self.counters.allocations += 1;
+        self.counters.bytes_in_use += layout.size();
+        self.counters.peak_bytes_in_use = self
+            .counters
+            .peak_bytes_in_use
+            .max(self.counters.bytes_in_use);
+
+        Some(ptr)
+    }
+
+    /// ## Safety
+    ///
+    /// `ptr` must have been returned by [`Self::allocate`] with the same `layout`.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        match size_class(&layout) {
+            Some(class) => unsafe { self.push_block(class, ptr) },
+            None => unsafe { self.large.deallocate(ptr, layout) },
+        }
+
+        self.counters.deallocations += 1;
+        self.counters.bytes_in_use -= layout.size();
+    }
+
+    fn pop_block(&mut self, class: usize) -> Option<NonNull<u8>> {
+        if self.free_lists[class].is_none() {
+            self.refill(class)?;
+        }
+
+        let block = self.free_lists[class].take()?;
+        self.free_lists[class] = block.next.take();
+        self.free_block_bytes -= SIZE_CLASSES[class];
+
+        Some(NonNull::from(block).cast())
+    }
+
+    #[deny(unsafe_op_in_unsafe_fn)]
+    unsafe fn push_block(&mut self, class: usize, ptr: NonNull<u8>) {
+        debug_assert!(mem::size_of::<FreeBlock>() <= SIZE_CLASSES[class]);
+
+        let block = ptr.cast::<FreeBlock>().as_ptr();
+        unsafe {
+            block.write(FreeBlock {
+                next: self.free_lists[class].take(),
+            });
+            self.free_lists[class] = Some(&mut *block);
+        }
+        self.free_block_bytes += SIZE_CLASSES[class];
+    }
+
+    /// Carves a new slab out of the large-object heap and splits it into blocks of `class`.
+    fn refill(&mut self, class: usize) -> Option<()> {
+        let block_size = SIZE_CLASSES[class];
+        let slab_layout = Layout::from_size_align(SLAB_SIZE, block_size).unwrap();
+        let slab = self.large.allocate(slab_layout)?;
+
+        for offset in (0..SLAB_SIZE).step_by(block_size).rev() {
+            let block = unsafe { NonNull::new_unchecked(slab.as_ptr().add(offset)) };
+            unsafe { self.push_block(class, block) };
+        }
+
+        Some(())
+    }
+}
@@ -1,58 +1,104 @@
-use conquer_once::spin::Lazy;
-use x86_64::{
-    structures::{
-        gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
-        tss::TaskStateSegment,
-    },
-    VirtAddr,
+use crate::memory::KernelStack;
+use alloc::boxed::Box;
+use conquer_once::spin::OnceCell;
+use core::mem;
+use klib::percpu;
+use x86_64::structures::{
+    gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
+    tss::TaskStateSegment,
 };
 
+/// The GDT of one CPU, which refers to the TSS of the CPU.
 struct GDTAndSelectors {
     gdt: GlobalDescriptorTable,
     selectors: Selectors,
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    data_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+/// The segment selectors, which are the same on every CPU. The user segments follow the kernel
+/// segments in the order `sysret` expects them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Selectors {
+    pub(crate) code_selector: SegmentSelector,
+    pub(crate) data_selector: SegmentSelector,
+    pub(crate) user_data_selector: SegmentSelector,
+    pub(crate) user_code_selector: SegmentSelector,
+    pub(crate) tss_selector: SegmentSelector,
 }
 
 pub(crate) const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+pub(crate) const PAGE_FAULT_IST_INDEX: u16 = 1;
+pub(crate) const NMI_IST_INDEX: u16 = 2;
+
+const EXCEPTION_STACK_SIZE: u64 = 4096 * 5;
+
+static SELECTORS: OnceCell<Selectors> = OnceCell::uninit();
+
+/// Creates a TSS with exception stacks of its own.
+fn create_tss() -> TaskStateSegment {
+    let double_fault_stack = KernelStack::new("double fault", EXCEPTION_STACK_SIZE)
+        .expect("failed to allocate the double fault stack");
+    // page faults get a stack of their own, so that a fault caused by a stack overflow can still
+    // be handled and reported
+    let page_fault_stack = KernelStack::new("page fault", EXCEPTION_STACK_SIZE)
+        .expect("failed to allocate the page fault stack");
+    // an NMI can arrive right before `sysretq`, while the stack pointer is already the one of user
+    // mode
+    let nmi_stack =
+        KernelStack::new("NMI", EXCEPTION_STACK_SIZE).expect("failed to allocate the NMI stack");
 
-static TSS: Lazy<TaskStateSegment> = Lazy::new(|| {
     let mut tss = TaskStateSegment::new();
-    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-        const STACK_SIZE: usize = 4096 * 5;
-        static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-        let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-        let stack_end = stack_start + STACK_SIZE;
-        stack_end
-    };
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = double_fault_stack.top();
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = page_fault_stack.top();
+    tss.interrupt_stack_table[NMI_IST_INDEX as usize] = nmi_stack.top();
+
+    // the stacks are used for as long as the CPU runs
+    mem::forget(double_fault_stack);
+    mem::forget(page_fault_stack);
+    mem::forget(nmi_stack);
+
     tss
-});
+}
 
-static GDT_AND_SELECTORS: Lazy<GDTAndSelectors> = Lazy::new(|| {
+/// ## Safety
+///
+/// `tss` must stay valid for as long as the GDT is loaded.
+#[deny(unsafe_op_in_unsafe_fn)]
+unsafe fn create_gdt(tss: *const TaskStateSegment) -> GDTAndSelectors {
     let mut gdt = GlobalDescriptorTable::new();
     let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
     let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
-    let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+    let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+    let tss_selector = gdt.add_entry(unsafe { Descriptor::tss_segment_unchecked(tss) });
     GDTAndSelectors {
         gdt,
         selectors: Selectors {
             code_selector,
             data_selector,
+            user_data_selector,
+            user_code_selector,
             tss_selector,
         },
     }
-});
+}
 
+/// Loads a GDT and TSS on the current CPU. Every CPU needs its own, because a loaded TSS is
+/// marked busy and holds the stacks of the exceptions on the CPU. They are never freed.
+///
+/// The per-CPU data must be installed, since it keeps the TSS for
+/// [`percpu::PerCpu::set_kernel_stack`].
 pub(crate) fn init() {
     use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
     use x86_64::instructions::tables::load_tss;
 
-    let GDTAndSelectors { gdt, selectors } = GDT_AND_SELECTORS.as_ref();
+    // the kernel stack for ring transitions changes while the TSS is loaded, so there is no
+    // shared reference to it
+    let tss = Box::into_raw(Box::new(create_tss()));
+    let tables: &'static GDTAndSelectors = Box::leak(Box::new(unsafe { create_gdt(tss) }));
+    let GDTAndSelectors { gdt, selectors } = tables;
+
+    let shared_selectors = SELECTORS.get_or_init(|| *selectors);
+    debug_assert_eq!(shared_selectors, selectors);
 
     gdt.load();
 
@@ -62,5 +108,11 @@ pub(crate) fn init() {
         ES::set_reg(selectors.data_selector);
         SS::set_reg(selectors.data_selector);
         load_tss(selectors.tss_selector);
+        percpu::current().set_tss(tss);
     }
 }
+
+/// The segment selectors of the GDTs loaded by [`init`].
+pub(crate) fn selectors() -> &'static Selectors {
+    SELECTORS.get().expect("GDT is not initialized")
+}
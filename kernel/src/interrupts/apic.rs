@@ -0,0 +1,255 @@
+use core::arch::x86_64::__cpuid;
+use x86_64::{registers::model_specific::Msr, PhysAddr};
+
+use crate::{
+    acpi::InterruptSourceOverride,
+    memory::{ioremap, CacheMode, IoMem, VmmError},
+};
+
+const IA32_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+// local APIC registers
+const LOCAL_APIC_ID: usize = 0x20;
+const TASK_PRIORITY: usize = 0x80;
+const END_OF_INTERRUPT: usize = 0xb0;
+const SPURIOUS_INTERRUPT_VECTOR: usize = 0xf0;
+const ERROR_STATUS: usize = 0x280;
+const INTERRUPT_COMMAND_LOW: usize = 0x300;
+const INTERRUPT_COMMAND_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const LVT_LINT0: usize = 0x350;
+const LVT_LINT1: usize = 0x360;
+const LVT_ERROR: usize = 0x370;
+const LOCAL_APIC_SIZE: usize = 0x400;
+
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_DELIVERY_NMI: u32 = 0b100 << 8;
+
+const IPI_DELIVERY_INIT: u32 = 0b101 << 8;
+const IPI_DELIVERY_STARTUP: u32 = 0b110 << 8;
+const IPI_DELIVERY_PENDING: u32 = 1 << 12;
+const IPI_LEVEL_ASSERT: u32 = 1 << 14;
+
+// I/O APIC registers
+const IO_REGISTER_SELECT: usize = 0x00;
+const IO_WINDOW: usize = 0x10;
+const IO_APIC_VERSION: u32 = 0x01;
+const IO_REDIRECTION_TABLE: u32 = 0x10;
+const IO_APIC_SIZE: usize = 0x20;
+
+const REDIRECTION_ACTIVE_LOW: u32 = 1 << 13;
+const REDIRECTION_LEVEL_TRIGGERED: u32 = 1 << 14;
+const REDIRECTION_MASKED: u32 = 1 << 16;
+
+/// Physical address of the I/O APIC on PC compatible systems, used if there is no MADT.
+pub const DEFAULT_IO_APIC_ADDRESS: PhysAddr = PhysAddr::new(0xfec0_0000);
+
+/// On PC compatible systems the PIT is connected to input 2 of the I/O APIC instead of 0. Used
+/// if there is no MADT.
+pub const DEFAULT_INTERRUPT_SOURCE_OVERRIDES: [InterruptSourceOverride; 1] =
+    [InterruptSourceOverride {
+        irq: 0,
+        gsi: 2,
+        active_low: false,
+        level_triggered: false,
+    }];
+
+/// Whether the current CPU has a local APIC.
+pub fn is_supported() -> bool {
+    const CPUID_FEATURE_APIC: u32 = 1 << 9;
+
+    __cpuid(1).edx & CPUID_FEATURE_APIC != 0
+}
+
+/// The local APIC of the CPU that accesses it. All local APICs are at the same physical
+/// address, so one mapping serves every CPU.
+pub struct LocalApic {
+    registers: IoMem,
+}
+
+impl LocalApic {
+    /// Maps the local APIC and enables the one of the current CPU like [`Self::enable`].
+    ///
+    /// ## Safety
+    ///
+    /// The CPU must support an APIC and the legacy PIC must be disabled.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn new(spurious_vector: u8) -> Result<Self, VmmError> {
+        let apic_base = unsafe { Msr::new(IA32_APIC_BASE).read() };
+
+        let physical_address = PhysAddr::new(apic_base & APIC_BASE_ADDRESS_MASK);
+        let registers = unsafe { ioremap(physical_address, LOCAL_APIC_SIZE, CacheMode::Uncached)? };
+        let local_apic = Self { registers };
+        unsafe { local_apic.enable(spurious_vector) };
+
+        Ok(local_apic)
+    }
+
+    /// Enables the local APIC of the current CPU. Its timer and local interrupt pins are masked,
+    /// except for LINT1 which delivers NMIs.
+    ///
+    /// ## Safety
+    ///
+    /// The CPU must support an APIC at the mapped address.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn enable(&self, spurious_vector: u8) {
+        let mut apic_base = Msr::new(IA32_APIC_BASE);
+        unsafe {
+            let value = apic_base.read();
+            apic_base.write(value | APIC_GLOBAL_ENABLE);
+        }
+
+        self.write(LVT_TIMER, LVT_MASKED);
+        self.write(LVT_LINT0, LVT_MASKED);
+        self.write(LVT_LINT1, LVT_DELIVERY_NMI);
+        self.write(LVT_ERROR, LVT_MASKED);
+
+        // the error status register has to be written before it is read
+        self.write(ERROR_STATUS, 0);
+        self.write(ERROR_STATUS, 0);
+
+        self.write(TASK_PRIORITY, 0);
+        self.write(
+            SPURIOUS_INTERRUPT_VECTOR,
+            APIC_SOFTWARE_ENABLE | spurious_vector as u32,
+        );
+    }
+
+    pub fn id(&self) -> u8 {
+        (self.read(LOCAL_APIC_ID) >> 24) as u8
+    }
+
+    pub fn end_of_interrupt(&self) {
+        self.write(END_OF_INTERRUPT, 0);
+    }
+
+    /// Sends an INIT IPI, which resets the CPU with the local APIC ID `destination` to wait for a
+    /// startup IPI.
+    pub fn send_init(&self, destination: u8) {
+        self.send_ipi(destination, IPI_DELIVERY_INIT | IPI_LEVEL_ASSERT);
+    }
+
+    /// Sends a startup IPI, which makes a CPU waiting after an INIT IPI start executing in real
+    /// mode at the physical address `page * 4096`.
+    pub fn send_startup(&self, destination: u8, page: u8) {
+        self.send_ipi(
+            destination,
+            IPI_DELIVERY_STARTUP | IPI_LEVEL_ASSERT | page as u32,
+        );
+    }
+
+    fn send_ipi(&self, destination: u8, command: u32) {
+        self.write(INTERRUPT_COMMAND_HIGH, (destination as u32) << 24);
+        // writing the low half sends the interrupt
+        self.write(INTERRUPT_COMMAND_LOW, command);
+
+        while self.read(INTERRUPT_COMMAND_LOW) & IPI_DELIVERY_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        self.registers.read(register)
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        self.registers.write(register, value);
+    }
+}
+
+/// An I/O APIC, which routes the global system interrupts starting at `gsi_base` to local APICs.
+pub struct IoApic {
+    registers: IoMem,
+    gsi_base: u32,
+    input_count: u32,
+}
+
+impl IoApic {
+    /// Maps the I/O APIC at `physical_address` and masks all of its inputs.
+    ///
+    /// ## Safety
+    ///
+    /// An I/O APIC must be located at `physical_address`.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn new(physical_address: PhysAddr, gsi_base: u32) -> Result<Self, VmmError> {
+        let registers = unsafe { ioremap(physical_address, IO_APIC_SIZE, CacheMode::Uncached)? };
+
+        let mut io_apic = Self {
+            registers,
+            gsi_base,
+            input_count: 0,
+        };
+        io_apic.input_count = ((io_apic.read(IO_APIC_VERSION) >> 16) & 0xff) + 1;
+
+        for input in 0..io_apic.input_count {
+            io_apic.write(IO_REDIRECTION_TABLE + input * 2, REDIRECTION_MASKED);
+        }
+
+        Ok(io_apic)
+    }
+
+    pub fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.input_count).contains(&gsi)
+    }
+
+    /// Delivers the global system interrupt `gsi` as `vector` to the local APIC with the ID
+    /// `destination`. The input stays masked until it is unmasked with [`Self::set_masked`].
+    pub fn route(
+        &mut self,
+        gsi: u32,
+        vector: u8,
+        destination: u8,
+        active_low: bool,
+        level_triggered: bool,
+    ) {
+        let mut low = vector as u32 | REDIRECTION_MASKED;
+        if active_low {
+            low |= REDIRECTION_ACTIVE_LOW;
+        }
+        if level_triggered {
+            low |= REDIRECTION_LEVEL_TRIGGERED;
+        }
+
+        let register = self.redirection_register(gsi);
+        self.write(register + 1, (destination as u32) << 24);
+        self.write(register, low);
+    }
+
+    pub fn set_masked(&mut self, gsi: u32, masked: bool) {
+        let register = self.redirection_register(gsi);
+        let low = self.read(register);
+
+        if masked {
+            self.write(register, low | REDIRECTION_MASKED);
+        } else {
+            self.write(register, low & !REDIRECTION_MASKED);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_masked(&mut self, gsi: u32) -> bool {
+        let register = self.redirection_register(gsi);
+        self.read(register) & REDIRECTION_MASKED != 0
+    }
+
+    fn redirection_register(&self, gsi: u32) -> u32 {
+        assert!(
+            self.handles(gsi),
+            "GSI {gsi} is not handled by this I/O APIC"
+        );
+        IO_REDIRECTION_TABLE + (gsi - self.gsi_base) * 2
+    }
+
+    fn read(&mut self, register: u32) -> u32 {
+        self.registers.write(IO_REGISTER_SELECT, register);
+        self.registers.read(IO_WINDOW)
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        self.registers.write(IO_REGISTER_SELECT, register);
+        self.registers.write(IO_WINDOW, value);
+    }
+}
@@ -0,0 +1,211 @@
+use alloc::{vec, vec::Vec};
+use conquer_once::spin::OnceCell;
+use klib::interrupts::UninterruptibleMutex;
+use pic8259::ChainedPics;
+
+use super::{
+    apic::{self, IoApic, LocalApic, DEFAULT_INTERRUPT_SOURCE_OVERRIDES, DEFAULT_IO_APIC_ADDRESS},
+    irq::IRQ_COUNT,
+    IRQ_BASE, PIC_1_OFFSET, PIC_2_OFFSET, SPURIOUS_INTERRUPT_VECTOR,
+};
+use crate::{
+    acpi::{self, InterruptSourceOverride, Madt},
+    memory::VmmError,
+};
+
+static CONTROLLER: OnceCell<InterruptController> = OnceCell::uninit();
+
+/// The controller that delivers hardware interrupts. ISA IRQ `n` is always delivered as vector
+/// `IRQ_BASE + n`.
+pub(crate) enum InterruptController {
+    Pic(UninterruptibleMutex<ChainedPics>),
+    Apic {
+        local_apic: LocalApic,
+        io_apics: UninterruptibleMutex<Vec<IoApic>>,
+        overrides: Vec<InterruptSourceOverride>,
+    },
+}
+
+pub(crate) fn controller() -> &'static InterruptController {
+    CONTROLLER
+        .get()
+        .expect("interrupt controller is not initialized")
+}
+
+/// Sets up the APIC if the CPU has one and the 8259 PIC otherwise. The I/O APICs are taken from
+/// the MADT if there is one. All IRQs start masked.
+pub(super) fn init() {
+    CONTROLLER.init_once(|| {
+        let mut pics = unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) };
+        // remapping the PIC keeps its spurious interrupts away from the exception vectors even
+        // if it is disabled
+        unsafe { pics.initialize() };
+
+        let madt = acpi::tables().and_then(|tables| tables.madt.as_ref());
+        let has_io_apic = madt.is_none_or(|madt| !madt.io_apics.is_empty());
+
+        if apic::is_supported() && has_io_apic {
+            unsafe { pics.disable() };
+
+            match unsafe { InterruptController::new_apic(madt) } {
+                Ok(controller) => {
+                    log::info!("Interrupt controller: APIC");
+                    return controller;
+                }
+                Err(error) => log::warn!("APIC initialization failed: {error}"),
+            }
+        }
+
+        unsafe { pics.write_masks(0xff, 0xff) };
+        log::info!("Interrupt controller: 8259 PIC");
+        InterruptController::Pic(UninterruptibleMutex::new(pics))
+    });
+}
+
+/// Enables the local APIC of an application processor. The 8259 PIC only delivers interrupts to
+/// the BSP, so there is nothing to do with it.
+pub(super) fn init_ap() {
+    if let InterruptController::Apic { local_apic, .. } = controller() {
+        unsafe { local_apic.enable(SPURIOUS_INTERRUPT_VECTOR) };
+    }
+}
+
+impl InterruptController {
+    /// ## Safety
+    ///
+    /// The CPU must have an APIC and the PIC must be disabled.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    unsafe fn new_apic(madt: Option<&Madt>) -> Result<Self, VmmError> {
+        let local_apic = unsafe { LocalApic::new(SPURIOUS_INTERRUPT_VECTOR)? };
+
+        let (io_apics, overrides) = match madt {
+            Some(madt) => (
+                madt.io_apics
+                    .iter()
+                    .map(|info| unsafe { IoApic::new(info.address, info.gsi_base) })
+                    .collect::<Result<_, _>>()?,
+                madt.interrupt_source_overrides.clone(),
+            ),
+            None => (
+                vec![unsafe { IoApic::new(DEFAULT_IO_APIC_ADDRESS, 0)? }],
+                Vec::from(DEFAULT_INTERRUPT_SOURCE_OVERRIDES),
+            ),
+        };
+
+        Ok(Self::Apic {
+            local_apic,
+            io_apics: UninterruptibleMutex::new(io_apics),
+            overrides,
+        })
+    }
+
+    /// The local APIC of the current CPU, or `None` if interrupts are delivered by the PIC.
+    pub fn local_apic(&self) -> Option<&LocalApic> {
+        match self {
+            Self::Pic(_) => None,
+            Self::Apic { local_apic, .. } => Some(local_apic),
+        }
+    }
+
+    /// Signals the end of the handler of `vector` to the controller that delivered it.
+    pub fn end_of_interrupt(&self, vector: u8) {
+        match self {
+            Self::Pic(pics) => unsafe { pics.lock().notify_end_of_interrupt(vector) },
+            Self::Apic { local_apic, .. } => local_apic.end_of_interrupt(),
+        }
+    }
+
+    /// Number of IRQ lines the controller can deliver.
+    pub fn irq_count(&self) -> usize {
+        match self {
+            Self::Pic(_) => 16,
+            Self::Apic { .. } => IRQ_COUNT,
+        }
+    }
+
+    /// Unmasks the interrupt `irq`.
+    pub fn enable_irq(&self, irq: u8) {
+        self.set_irq_masked(irq, false);
+    }
+
+    /// Masks the interrupt `irq`.
+    pub fn disable_irq(&self, irq: u8) {
+        self.set_irq_masked(irq, true);
+    }
+
+    /// Whether the interrupt `irq` is masked, so that it is never delivered.
+    #[cfg(test)]
+    pub fn is_irq_masked(&self, irq: u8) -> bool {
+        match self {
+            Self::Pic(pics) => {
+                let [mask1, mask2] = unsafe { pics.lock().read_masks() };
+                (mask1 as u16 | (mask2 as u16) << 8) & (1 << irq) != 0
+            }
+            Self::Apic {
+                io_apics,
+                overrides,
+                ..
+            } => {
+                let gsi = overrides
+                    .iter()
+                    .find(|o| o.irq == irq)
+                    .map_or(irq as u32, |o| o.gsi);
+
+                io_apics
+                    .lock()
+                    .iter_mut()
+                    .find(|io_apic| io_apic.handles(gsi))
+                    .expect("no I/O APIC handles the IRQ")
+                    .is_masked(gsi)
+            }
+        }
+    }
+
+    fn set_irq_masked(&self, irq: u8, masked: bool) {
+        match self {
+            Self::Pic(pics) => {
+                let mut pics = pics.lock();
+                let [mask1, mask2] = unsafe { pics.read_masks() };
+
+                let mut masks = mask1 as u16 | (mask2 as u16) << 8;
+                if masked {
+                    masks |= 1 << irq;
+                } else {
+                    masks &= !(1 << irq);
+                    if irq >= 8 {
+                        // the secondary PIC is cascaded through IRQ 2 of the primary one
+                        masks &= !(1 << 2);
+                    }
+                }
+                unsafe { pics.write_masks(masks as u8, (masks >> 8) as u8) };
+            }
+            Self::Apic {
+                local_apic,
+                io_apics,
+                overrides,
+            } => {
+                let source_override = overrides.iter().find(|o| o.irq == irq);
+                let gsi = source_override.map_or(irq as u32, |o| o.gsi);
+                let active_low = source_override.is_some_and(|o| o.active_low);
+                let level_triggered = source_override.is_some_and(|o| o.level_triggered);
+
+                let mut io_apics = io_apics.lock();
+                let io_apic = io_apics
+                    .iter_mut()
+                    .find(|io_apic| io_apic.handles(gsi))
+                    .expect("no I/O APIC handles the IRQ");
+
+                if !masked {
+                    io_apic.route(
+                        gsi,
+                        IRQ_BASE + irq,
+                        local_apic.id(),
+                        active_low,
+                        level_triggered,
+                    );
+                }
+                io_apic.set_masked(gsi, masked);
+            }
+        }
+    }
+}
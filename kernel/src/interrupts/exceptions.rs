@@ -0,0 +1,420 @@
+use crate::{
+    gdt,
+    memory::{self, VmmError},
+    process, thread,
+    user::{self, ExitStatus},
+};
+use core::fmt;
+use klib::percpu::KernelGs;
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr2, Cr3, Cr4},
+        model_specific::Efer,
+    },
+    structures::idt::{
+        DescriptorTable, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+        SelectorErrorCode,
+    },
+};
+
+/// Dumps the registers and panics with the name of the exception and optional details.
+macro_rules! fatal_exception {
+    ($name:expr, $stack_frame:expr) => {{
+        dump_registers(&$stack_frame);
+        panic!("Exception: {}", $name)
+    }};
+    ($name:expr, $stack_frame:expr, $($details:tt)+) => {{
+        dump_registers(&$stack_frame);
+        panic!("Exception: {}\n{}", $name, format_args!($($details)+))
+    }};
+}
+
+/// Kills the program running in user mode if the exception interrupted it, since only the
+/// program is affected then. Does nothing if the exception interrupted the kernel.
+macro_rules! kill_user_program {
+    ($name:expr, $stack_frame:expr) => {
+        if interrupted_user_mode(&$stack_frame) {
+            log::error!("Program {} killed by exception: {}", CurrentProgram, $name);
+            dump_registers(&$stack_frame);
+            unsafe { user::exit(ExitStatus::Killed) };
+        }
+    };
+    ($name:expr, $stack_frame:expr, $($details:tt)+) => {
+        if interrupted_user_mode(&$stack_frame) {
+            log::error!(
+                "Program {} killed by exception: {}\n{}",
+                CurrentProgram,
+                $name,
+                format_args!($($details)+)
+            );
+            dump_registers(&$stack_frame);
+            unsafe { user::exit(ExitStatus::Killed) };
+        }
+    };
+}
+
+/// Defines a handler for an exception the kernel cannot recover from. In user mode, it only
+/// kills the thread.
+macro_rules! fatal_exception_handler {
+    ($handler:ident, $name:literal) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame) {
+            let _gs = unsafe { KernelGs::enter(stack_frame.code_segment) };
+            kill_user_program!($name, stack_frame);
+            fatal_exception!($name, stack_frame);
+        }
+    };
+    ($handler:ident, $name:literal, error_code) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame, error_code: u64) {
+            let _gs = unsafe { KernelGs::enter(stack_frame.code_segment) };
+            kill_user_program!($name, stack_frame, "Error Code: {error_code:#x}");
+            fatal_exception!($name, stack_frame, "Error Code: {error_code:#x}");
+        }
+    };
+    ($handler:ident, $name:literal, selector_error_code) => {
+        extern "x86-interrupt" fn $handler(stack_frame: InterruptStackFrame, error_code: u64) {
+            let _gs = unsafe { KernelGs::enter(stack_frame.code_segment) };
+            let selector = SelectorErrorCode::new_truncate(error_code);
+            kill_user_program!(
+                $name,
+                stack_frame,
+                "Error Code: {error_code:#x} ({})",
+                SelectorDescription(selector)
+            );
+            fatal_exception!(
+                $name,
+                stack_frame,
+                "Error Code: {error_code:#x} ({})",
+                SelectorDescription(selector)
+            );
+        }
+    };
+}
+
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    unsafe {
+        idt.non_maskable_interrupt
+            .set_handler_fn(non_maskable_interrupt_handler)
+            .set_stack_index(gdt::NMI_IST_INDEX)
+    };
+    idt.breakpoint.set_handler_fn(breakpoint_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded
+        .set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available
+        .set_handler_fn(device_not_available_handler);
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(double_fault_handler)
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX)
+    };
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present
+        .set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault
+        .set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler);
+    unsafe {
+        idt.page_fault
+            .set_handler_fn(page_fault_handler)
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX)
+    };
+    idt.x87_floating_point
+        .set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point
+        .set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.cp_protection_exception
+        .set_handler_fn(control_protection_handler);
+    idt.hv_injection_exception
+        .set_handler_fn(hypervisor_injection_handler);
+    idt.vmm_communication_exception
+        .set_handler_fn(vmm_communication_handler);
+    idt.security_exception.set_handler_fn(security_handler);
+}
+
+/// Whether the exception interrupted code running in ring 3.
+fn interrupted_user_mode(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 0b11 == 3
+}
+
+/// Logs the interrupted context and the control registers.
+fn dump_registers(stack_frame: &InterruptStackFrame) {
+    log::error!(
+        "Registers:\n\
+        RIP  {:#018x}  CS {:#06x}  RFLAGS {:#010x}\n\
+        RSP  {:#018x}  SS {:#06x}\n\
+        CR0  {:#018x}  CR2 {:#018x}\n\
+        CR3  {:#018x}  CR4 {:#018x}\n\
+        EFER {:#018x}",
+        stack_frame.instruction_pointer.as_u64(),
+        stack_frame.code_segment,
+        stack_frame.cpu_flags,
+        stack_frame.stack_pointer.as_u64(),
+        stack_frame.stack_segment,
+        Cr0::read_raw(),
+        Cr2::read().as_u64(),
+        Cr3::read().0.start_address().as_u64(),
+        Cr4::read_raw(),
+        Efer::read_raw(),
+    );
+}
+
+fatal_exception_handler!(divide_error_handler, "Divide error");
+fatal_exception_handler!(overflow_handler, "Overflow");
+fatal_exception_handler!(bound_range_exceeded_handler, "Bound range exceeded");
+fatal_exception_handler!(invalid_opcode_handler, "Invalid opcode");
+fatal_exception_handler!(device_not_available_handler, "Device not available");
+fatal_exception_handler!(invalid_tss_handler, "Invalid TSS", selector_error_code);
+fatal_exception_handler!(
+    segment_not_present_handler,
+    "Segment not present",
+    selector_error_code
+);
+fatal_exception_handler!(
+    stack_segment_fault_handler,
+    "Stack segment fault",
+    selector_error_code
+);
+fatal_exception_handler!(
+    general_protection_fault_handler,
+    "General protection fault",
+    selector_error_code
+);
+fatal_exception_handler!(x87_floating_point_handler, "x87 floating point");
+fatal_exception_handler!(alignment_check_handler, "Alignment check", error_code);
+fatal_exception_handler!(simd_floating_point_handler, "SIMD floating point");
+fatal_exception_handler!(virtualization_handler, "Virtualization");
+fatal_exception_handler!(hypervisor_injection_handler, "Hypervisor injection");
+fatal_exception_handler!(vmm_communication_handler, "VMM communication", error_code);
+fatal_exception_handler!(security_handler, "Security", error_code);
+
+extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    let _gs = unsafe { KernelGs::enter(stack_frame.code_segment) };
+    log::warn!("Exception: Debug\n{stack_frame:#?}");
+}
+
+extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: InterruptStackFrame) {
+    let _gs = unsafe { KernelGs::ensure() };
+    log::error!("Exception: Non-maskable interrupt");
+    dump_registers(&stack_frame);
+}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    let _gs = unsafe { KernelGs::enter(stack_frame.code_segment) };
+    log::warn!("Exception: Breakpoint\n{stack_frame:#?}");
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    let _gs = unsafe { KernelGs::ensure() };
+    fatal_exception!("Double fault", stack_frame);
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let _gs = unsafe { KernelGs::enter(stack_frame.code_segment) };
+    let cr2_value = Cr2::read();
+
+    // the reference to the process is dropped before the program is killed, which never returns
+    if interrupted_user_mode(&stack_frame) {
+        let result = process::current()
+            .ok_or(VmmError::NotMapped(cr2_value))
+            .and_then(|process| {
+                process
+                    .address_space()
+                    .handle_page_fault(cr2_value, error_code)
+            });
+        match result {
+            Ok(()) => return,
+            Err(error) => kill_user_program!(
+                "PAGE FAULT",
+                stack_frame,
+                "Accessed Address: {cr2_value:?} ({error})\n\
+                Error Code: {:#x} ({})",
+                error_code.bits(),
+                PageFaultDescription(error_code)
+            ),
+        }
+    }
+
+    if let Some(stack) = memory::overflowed_stack(cr2_value) {
+        fatal_exception!(
+            "PAGE FAULT",
+            stack_frame,
+            "kernel stack overflow in the {stack} stack\n\
+            Accessed Address: {cr2_value:?}"
+        );
+    }
+
+    fatal_exception!(
+        "PAGE FAULT",
+        stack_frame,
+        "Accessed Address: {cr2_value:?}\n\
+        Error Code: {:#x} ({})",
+        error_code.bits(),
+        PageFaultDescription(error_code)
+    );
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    let _gs = unsafe { KernelGs::ensure() };
+    fatal_exception!("Machine check", stack_frame);
+}
+
+extern "x86-interrupt" fn control_protection_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let _gs = unsafe { KernelGs::enter(stack_frame.code_segment) };
+    let cause = match error_code & 0x7fff {
+        1 => "near return",
+        2 => "far return or interrupt return",
+        3 => "missing end branch",
+        4 => "shadow stack restore",
+        5 => "shadow stack busy flag",
+        _ => "unknown cause",
+    };
+    kill_user_program!(
+        "Control protection",
+        stack_frame,
+        "Error Code: {error_code:#x} ({cause})"
+    );
+
+    fatal_exception!(
+        "Control protection",
+        stack_frame,
+        "Error Code: {error_code:#x} ({cause})"
+    );
+}
+
+/// Names the process and thread of the program running on the current CPU.
+struct CurrentProgram;
+
+impl fmt::Display for CurrentProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match process::current() {
+            Some(process) => write!(
+                f,
+                "{} (process {}, thread {})",
+                process.name(),
+                process.id(),
+                thread::current()
+            ),
+            None => write!(f, "in thread {}", thread::current()),
+        }
+    }
+}
+
+/// Describes the segment selector referenced by the error code of a segment related exception.
+struct SelectorDescription(SelectorErrorCode);
+
+impl fmt::Display for SelectorDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let selector = self.0;
+
+        if selector.is_null() {
+            return write!(f, "not caused by a selector");
+        }
+
+        let table = match selector.descriptor_table() {
+            DescriptorTable::Gdt => "GDT",
+            DescriptorTable::Idt => "IDT",
+            DescriptorTable::Ldt => "LDT",
+        };
+        write!(f, "{table} entry {}", selector.index())?;
+
+        if selector.external() {
+            write!(f, " while delivering an external event")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Describes the access that caused a page fault as a sentence.
+struct PageFaultDescription(PageFaultErrorCode);
+
+impl fmt::Display for PageFaultDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let error_code = self.0;
+
+        let mode = if error_code.contains(PageFaultErrorCode::USER_MODE) {
+            "user"
+        } else {
+            "kernel"
+        };
+        let access = if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            "instruction fetch from"
+        } else if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE) {
+            "write to"
+        } else {
+            "read from"
+        };
+        let page = if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            "a page that does not permit it"
+        } else {
+            "a non-present page"
+        };
+        write!(f, "{mode}-mode {access} {page}")?;
+
+        let remarks = [
+            (
+                PageFaultErrorCode::MALFORMED_TABLE,
+                "reserved bits set in a page table entry",
+            ),
+            (
+                PageFaultErrorCode::PROTECTION_KEY,
+                "blocked by a protection key",
+            ),
+            (PageFaultErrorCode::SHADOW_STACK, "shadow stack access"),
+            (PageFaultErrorCode::SGX, "SGX access violation"),
+        ];
+        for (flag, remark) in remarks {
+            if error_code.contains(flag) {
+                write!(f, ", {remark}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[test_case]
+fn error_codes_are_decoded() {
+    use alloc::string::ToString;
+
+    let selector = SelectorErrorCode::new_truncate(0x29);
+    assert_eq!(
+        SelectorDescription(selector).to_string(),
+        "GDT entry 5 while delivering an external event"
+    );
+    let selector = SelectorErrorCode::new_truncate(0);
+    assert_eq!(
+        SelectorDescription(selector).to_string(),
+        "not caused by a selector"
+    );
+
+    let error_code = PageFaultErrorCode::USER_MODE | PageFaultErrorCode::CAUSED_BY_WRITE;
+    assert_eq!(
+        PageFaultDescription(error_code).to_string(),
+        "user-mode write to a non-present page"
+    );
+    let error_code = PageFaultErrorCode::PROTECTION_VIOLATION
+        | PageFaultErrorCode::INSTRUCTION_FETCH
+        | PageFaultErrorCode::MALFORMED_TABLE;
+    assert_eq!(
+        PageFaultDescription(error_code).to_string(),
+        "kernel-mode instruction fetch from a page that does not permit it, \
+        reserved bits set in a page table entry"
+    );
+}
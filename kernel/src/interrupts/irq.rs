@@ -0,0 +1,244 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use klib::{
+    interrupts::UninterruptibleMutex,
+    percpu::{self, KernelGs},
+};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+use super::{controller, IRQ_BASE};
+use crate::thread;
+
+/// Number of IRQ lines handlers can be registered for. The PIC only has the first 16.
+pub const IRQ_COUNT: usize = 24;
+
+pub const TIMER_IRQ: u8 = 0;
+pub const KEYBOARD_IRQ: u8 = 1;
+
+static HANDLERS: [UninterruptibleMutex<Vec<RegisteredHandler>>; IRQ_COUNT] =
+    [const { UninterruptibleMutex::new(Vec::new()) }; IRQ_COUNT];
+
+static NEXT_HANDLER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Tells the dispatcher whether the device of a handler raised the interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    NotHandled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqMode {
+    /// No other handler may be registered for the IRQ.
+    Exclusive,
+    /// The IRQ can be shared with other shared handlers, which are all called in order of
+    /// registration.
+    #[allow(dead_code)] // no shared device has a driver yet
+    Shared,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    InvalidIrq(u8),
+    /// The IRQ is used by a handler that does not share it with the new one.
+    Busy {
+        irq: u8,
+        owner: &'static str,
+    },
+}
+
+impl fmt::Display for IrqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidIrq(irq) => write!(f, "IRQ {irq} does not exist"),
+            Self::Busy { irq, owner } => write!(f, "IRQ {irq} is used by {owner}"),
+        }
+    }
+}
+
+/// Identifies a registered handler so that it can be unregistered.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IrqHandlerId {
+    irq: u8,
+    id: u64,
+}
+
+struct RegisteredHandler {
+    id: u64,
+    name: &'static str,
+    mode: IrqMode,
+    handler: Box<dyn FnMut() -> IrqReturn + Send>,
+}
+
+/// Registers `handler` for `irq` and unmasks the IRQ if it is the first handler.
+///
+/// The handler runs with interrupts disabled, and the end of the interrupt is signaled after all
+/// handlers of the IRQ have returned. It must not register or unregister handlers for its own IRQ
+/// because the handlers of an IRQ are locked while they run.
+pub fn register_irq_handler(
+    irq: u8,
+    name: &'static str,
+    mode: IrqMode,
+    handler: impl FnMut() -> IrqReturn + Send + 'static,
+) -> Result<IrqHandlerId, IrqError> {
+    if irq as usize >= controller().irq_count() {
+        return Err(IrqError::InvalidIrq(irq));
+    }
+
+    let handler = Box::new(handler);
+    let mut handlers = HANDLERS[irq as usize].lock();
+
+    if let Some(other) = handlers
+        .iter()
+        .find(|other| mode == IrqMode::Exclusive || other.mode == IrqMode::Exclusive)
+    {
+        return Err(IrqError::Busy {
+            irq,
+            owner: other.name,
+        });
+    }
+
+    let id = NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+    handlers.push(RegisteredHandler {
+        id,
+        name,
+        mode,
+        handler,
+    });
+
+    if handlers.len() == 1 {
+        controller().enable_irq(irq);
+    }
+
+    log::debug!("IRQ {irq} handler registered for {name}");
+    Ok(IrqHandlerId { irq, id })
+}
+
+/// Removes a handler and masks its IRQ if no handlers are left.
+///
+/// Must not be called by a handler of the same IRQ, which would deadlock.
+#[allow(dead_code)] // no driver can be unloaded yet
+pub fn unregister_irq_handler(handler_id: IrqHandlerId) {
+    let mut handlers = HANDLERS[handler_id.irq as usize].lock();
+    handlers.retain(|handler| handler.id != handler_id.id);
+
+    if handlers.is_empty() {
+        controller().disable_irq(handler_id.irq);
+    }
+}
+
+/// Calls all handlers of `irq` and returns whether one of them handled it.
+fn run_handlers(irq: u8) -> bool {
+    let mut handlers = HANDLERS[irq as usize].lock();
+
+    // every handler runs because the devices sharing the IRQ may have raised it at the same time
+    let mut handled = false;
+    for handler in handlers.iter_mut() {
+        handled |= (handler.handler)() == IrqReturn::Handled;
+    }
+    handled
+}
+
+fn dispatch(irq: u8) {
+    percpu::current().count_interrupt();
+
+    if !run_handlers(irq) {
+        log::trace!("Unhandled IRQ {irq}");
+    }
+
+    controller().end_of_interrupt(IRQ_BASE + irq);
+
+    // switching threads here is safe because no handler lock is held and the controller
+    // already accepts the next interrupt
+    thread::preempt();
+}
+
+macro_rules! irq_entry_points {
+    ($($irq:literal),*) => {
+        [$({
+            extern "x86-interrupt" fn entry_point(stack_frame: InterruptStackFrame) {
+                let _gs = unsafe { KernelGs::enter(stack_frame.code_segment) };
+                dispatch($irq);
+            }
+            entry_point as extern "x86-interrupt" fn(InterruptStackFrame)
+        }),*]
+    };
+}
+
+pub(super) fn set_handlers(idt: &mut InterruptDescriptorTable) {
+    let entry_points: [_; IRQ_COUNT] = irq_entry_points!(
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23
+    );
+
+    for (irq, entry_point) in entry_points.into_iter().enumerate() {
+        idt[IRQ_BASE as usize + irq].set_handler_fn(entry_point);
+    }
+}
+
+#[test_case]
+fn shared_handlers_are_chained() {
+    use alloc::sync::Arc;
+    use core::sync::atomic::AtomicUsize;
+
+    const IRQ: u8 = 5;
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let counter = |calls: &Arc<AtomicUsize>, result| {
+        let calls = calls.clone();
+        move || {
+            calls.fetch_add(1, Ordering::Relaxed);
+            result
+        }
+    };
+
+    let first = register_irq_handler(
+        IRQ,
+        "first",
+        IrqMode::Shared,
+        counter(&calls, IrqReturn::NotHandled),
+    )
+    .unwrap();
+    let second = register_irq_handler(
+        IRQ,
+        "second",
+        IrqMode::Shared,
+        counter(&calls, IrqReturn::Handled),
+    )
+    .unwrap();
+    assert_eq!(
+        register_irq_handler(IRQ, "exclusive", IrqMode::Exclusive, || IrqReturn::Handled),
+        Err(IrqError::Busy {
+            irq: IRQ,
+            owner: "first"
+        })
+    );
+
+    assert!(run_handlers(IRQ));
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    unregister_irq_handler(second);
+    assert!(!run_handlers(IRQ));
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+    unregister_irq_handler(first);
+}
+
+#[test_case]
+fn unregistering_the_last_handler_masks_the_irq() {
+    const IRQ: u8 = 6;
+    assert!(controller().is_irq_masked(IRQ));
+
+    let first = register_irq_handler(IRQ, "first", IrqMode::Shared, || IrqReturn::Handled).unwrap();
+    let second =
+        register_irq_handler(IRQ, "second", IrqMode::Shared, || IrqReturn::Handled).unwrap();
+    assert!(!controller().is_irq_masked(IRQ));
+
+    unregister_irq_handler(first);
+    assert!(!controller().is_irq_masked(IRQ));
+    unregister_irq_handler(second);
+    assert!(controller().is_irq_masked(IRQ));
+    assert!(HANDLERS[IRQ as usize].lock().is_empty());
+}
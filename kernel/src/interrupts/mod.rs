@@ -0,0 +1,47 @@
+mod apic;
+mod controller;
+mod exceptions;
+mod irq;
+
+use conquer_once::spin::Lazy;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+pub(crate) use apic::LocalApic;
+pub(crate) use controller::controller;
+pub use irq::{register_irq_handler, IrqMode, IrqReturn, KEYBOARD_IRQ, TIMER_IRQ};
+
+/// Vector of ISA IRQ 0. The others follow in order.
+pub(crate) const IRQ_BASE: u8 = 32;
+pub(crate) const PIC_1_OFFSET: u8 = IRQ_BASE;
+pub(crate) const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+pub(crate) const SPURIOUS_INTERRUPT_VECTOR: u8 = 0xff;
+
+static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(create_idt);
+
+fn create_idt() -> InterruptDescriptorTable {
+    let mut idt = InterruptDescriptorTable::new();
+
+    exceptions::set_handlers(&mut idt);
+    irq::set_handlers(&mut idt);
+    crate::syscall::set_interrupt_handler(&mut idt);
+    idt[SPURIOUS_INTERRUPT_VECTOR as usize].set_handler_fn(spurious_interrupt_handler);
+
+    idt
+}
+
+pub(crate) fn init() {
+    IDT.load();
+    controller::init();
+
+    x86_64::instructions::interrupts::enable();
+}
+
+/// Loads the IDT on an application processor and enables its local APIC. All CPUs share the
+/// IDT, since they handle interrupts the same way. Interrupts stay disabled.
+pub(crate) fn init_ap() {
+    IDT.load();
+    controller::init_ap();
+}
+
+/// Spurious interrupts of the local APIC must not be acknowledged.
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
@@ -1,14 +1,26 @@
-use crate::terminal::TERMINAL;
-use core::fmt::Write;
+use crate::{terminal::TERMINAL, time::SystemTime};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use klib::{interrupts::UninterruptibleMutex, io::Terminal};
 
 pub(crate) struct Logger<'a> {
     terminal: &'a UninterruptibleMutex<Terminal<'a>>,
+    /// Whether records are prefixed with the wall clock time.
+    timestamps: AtomicBool,
 }
 
 impl<'a> Logger<'a> {
     pub const fn new(terminal: &'a UninterruptibleMutex<Terminal<'a>>) -> Logger<'_> {
-        Self { terminal }
+        Self {
+            terminal,
+            timestamps: AtomicBool::new(false),
+        }
+    }
+
+    pub fn set_timestamps(&self, enabled: bool) {
+        self.timestamps.store(enabled, Ordering::Relaxed);
     }
 }
 
@@ -19,6 +31,9 @@ impl log::Log for Logger<'_> {
 
     fn log(&self, record: &log::Record) {
         let mut terminal = self.terminal.lock();
+        if self.timestamps.load(Ordering::Relaxed) {
+            write!(terminal, "[{}] ", SystemTime::now()).unwrap();
+        }
         writeln!(terminal, "{:5}: {}", record.level(), record.args()).unwrap();
     }
 
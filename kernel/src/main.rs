@@ -1,28 +1,37 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
 extern crate alloc;
 
+mod acpi;
 mod allocator;
 mod gdt;
 mod interrupts;
 mod logger;
 mod memory;
+mod process;
 mod serial;
+mod smp;
+mod syscall;
+mod task;
 mod terminal;
+mod thread;
+mod time;
+mod user;
 
 use alloc::vec;
 use alloc::{boxed::Box, rc::Rc, vec::Vec};
 use bootloader_api::{config::Mapping, BootInfo, BootloaderConfig};
 use core::panic::PanicInfo;
 use klib::io::{print, println};
-use x86_64::VirtAddr;
-
-use crate::memory::BootInfoFrameAllocator;
+use task::Executor;
+use thread::Priority;
+use x86_64::{PhysAddr, VirtAddr};
 
 #[cfg(not(test))]
 #[panic_handler]
@@ -31,9 +40,7 @@ fn panic(info: &PanicInfo) -> ! {
 
     log::error!("{info}");
 
-    loop {
-        x86_64::instructions::hlt();
-    }
+    thread::idle();
 }
 
 #[cfg(test)]
@@ -52,6 +59,9 @@ const BOOTLOADER_CONFIG: BootloaderConfig = {
     config
 };
 
+/// The clock source for timestamps, or `None` to select the best one available.
+const CLOCK_SOURCE: Option<time::ClockSourceKind> = None;
+
 #[cfg(not(test))]
 bootloader_api::entry_point!(kernel_main, config = &BOOTLOADER_CONFIG);
 
@@ -87,12 +97,37 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
         Rc::strong_count(&cloned_reference)
     );
 
-    // […] call `test_main` in test context
-    println!("It did not crash!");
+    let heap_statistics = allocator::statistics();
+    println!(
+        "heap: {} bytes used, {} bytes free (largest block {})",
+        heap_statistics.used_bytes,
+        heap_statistics.free_bytes,
+        allocator::largest_free_block()
+    );
 
-    loop {
-        x86_64::instructions::hlt();
+    // […] call `test_main` in test context
+    println!("It did not crash! (uptime {:?})", time::uptime());
+
+    // the executor handles the keyboard, so typing stays responsive while other threads run
+    thread::spawn_with_priority("executor", Priority::High, || {
+        let mut executor = Executor::new();
+        executor.spawn(task::keyboard::print_keypresses());
+        executor.run();
+    })
+    .expect("failed to spawn the executor thread");
+
+    match process::spawn("hello", user::HELLO, &["hello", "world"], &[]) {
+        Ok(hello) => {
+            thread::spawn("hello waiter", || {
+                log::info!("hello finished: {:?}", hello.join())
+            })
+            .expect("failed to spawn the hello waiter thread");
+        }
+        Err(error) => log::warn!("Failed to start hello: {error}"),
     }
+
+    // the idle thread takes over when nothing else is ready
+    thread::exit();
 }
 
 #[cfg(test)]
@@ -109,20 +144,51 @@ fn init(boot_info: &'static mut BootInfo) {
 
     let framebuffer_info = framebuffer.info().clone();
     let raw_frame_buffer = framebuffer.buffer_mut();
+    let framebuffer_start = VirtAddr::from_ptr(raw_frame_buffer.as_ptr());
+    let framebuffer_size = raw_frame_buffer.len();
 
     serial::init();
     terminal::init(raw_frame_buffer, framebuffer_info);
     logger::init();
-    gdt::init();
-    interrupts::init();
 
+    // the exception stacks are allocated from the kernel address space, so memory management
+    // has to be ready before the GDT and IDT are loaded
     let physical_memory_offset =
         VirtAddr::new(boot_info.physical_memory_offset.into_option().unwrap());
 
-    let mut mapper = unsafe { memory::init(physical_memory_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::new(&boot_info.memory_regions) };
+    unsafe { memory::init(physical_memory_offset, &boot_info.memory_regions) };
+    memory::register_boot_stack(BOOTLOADER_CONFIG.kernel_stack_size)
+        .expect("failed to register the boot stack");
+
+    // the bootloader maps the framebuffer write-back, so every pixel would go through the caches
+    let cache_mode = memory::CacheMode::WriteCombining;
+    if let Err(error) =
+        unsafe { memory::set_cache_mode(framebuffer_start, framebuffer_size, cache_mode) }
+    {
+        log::warn!("Failed to map the framebuffer write-combining: {error}");
+    }
+
+    allocator::init_heap(allocator::HEAP_MAX_SIZE).expect("heap initialization failed");
+    smp::init_bsp();
+
+    match boot_info.rsdp_addr.into_option() {
+        Some(rsdp_address) => {
+            if let Err(error) = unsafe { acpi::init(PhysAddr::new(rsdp_address)) } {
+                log::warn!("ACPI tables are unusable: {error}");
+            }
+        }
+        None => log::warn!("No ACPI tables found"),
+    }
 
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    gdt::init();
+    syscall::init();
+    interrupts::init();
+    task::keyboard::init();
+    time::init(CLOCK_SOURCE);
+    // the wall clock is only valid once it has been read from the RTC
+    logger::LOGGER.set_timestamps(true);
+    thread::init();
+    smp::init();
 }
 
 #[test_case]
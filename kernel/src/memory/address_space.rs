@@ -0,0 +1,666 @@
+use alloc::collections::BTreeMap;
+use conquer_once::spin::OnceCell;
+use core::{ops::Range, ptr};
+use klib::interrupts::UninterruptibleMutex;
+use x86_64::{
+    instructions::tlb,
+    registers::control::Cr3,
+    structures::{
+        idt::PageFaultErrorCode,
+        paging::{
+            mapper::{MapToError, MappedFrame, Translate, TranslateResult},
+            page_table::PageTableEntry,
+            FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+            PageTableFlags, PhysFrame, Size4KiB,
+        },
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::{
+    frame_allocator, mapper, physical_to_virtual, user::user_pages, VmmError, USER_LEVEL_4_ENTRIES,
+};
+
+/// Marks a page whose frame is shared with another address space. It is mapped read-only until
+/// it is written, which gives the writing address space a copy of the frame.
+const COPY_ON_WRITE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// The level 4 page table the bootloader set up, which kernel threads run with.
+static KERNEL_LEVEL_4_FRAME: OnceCell<PhysFrame> = OnceCell::uninit();
+
+/// How many address spaces map each frame that more than one of them maps since a fork.
+static SHARED_FRAMES: UninterruptibleMutex<BTreeMap<PhysFrame, usize>> =
+    UninterruptibleMutex::new(BTreeMap::new());
+
+/// The page tables of a process. The kernel entries of the level 4 table are copies of those of
+/// the kernel page table, which never change after boot, so the kernel is mapped the same way in
+/// every address space. Everything below the user entries belongs to the address space alone and
+/// is freed with it.
+///
+/// User memory is made up of regions, whose pages get a zeroed frame when they are first
+/// accessed.
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+    regions: BTreeMap<VirtAddr, UserRegion>,
+}
+
+/// Whole pages of user memory that user mode can access with the same flags.
+#[derive(Debug, Clone, Copy)]
+struct UserRegion {
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+}
+
+pub(super) fn init() {
+    KERNEL_LEVEL_4_FRAME.init_once(|| Cr3::read().0);
+}
+
+impl UserRegion {
+    fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    fn contains(&self, address: VirtAddr) -> bool {
+        self.start <= address && address < self.end()
+    }
+
+    /// The flags of the pages of the region whose frames are not shared.
+    fn page_flags(&self) -> PageTableFlags {
+        self.flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE
+    }
+}
+
+impl AddressSpace {
+    /// Creates an address space with nothing mapped in user space.
+    pub fn new() -> Result<Self, VmmError> {
+        let level_4_frame = frame_allocator()
+            .allocate_frame()
+            .ok_or(VmmError::Map(MapToError::FrameAllocationFailed))?;
+
+        let mut mapper = mapper();
+        let kernel_table = mapper.level_4_table();
+        let table = unsafe { &mut *page_table(level_4_frame) };
+
+        for (index, entry) in table.iter_mut().enumerate() {
+            if USER_LEVEL_4_ENTRIES.contains(&index) {
+                entry.set_unused();
+            } else {
+                *entry = kernel_table[index].clone();
+            }
+        }
+
+        Ok(Self {
+            level_4_frame,
+            regions: BTreeMap::new(),
+        })
+    }
+
+    /// Adds a region of `size` bytes at `start` (extended to whole pages) that user mode can
+    /// access with `flags`. Its pages are mapped to zeroed frames when they are first accessed.
+    pub fn map(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        flags: PageTableFlags,
+    ) -> Result<(), VmmError> {
+        let pages = user_pages(start, size)?;
+        let region = UserRegion {
+            start: pages.start.start_address(),
+            size: (pages.end - pages.start) * Size4KiB::SIZE,
+            flags,
+        };
+
+        // regions do not overlap, so the one that starts last before the end ends last
+        let overlapping = self
+            .regions
+            .range(..region.end())
+            .next_back()
+            .is_some_and(|(_, other)| other.end() > region.start);
+        if overlapping {
+            return Err(VmmError::Overlapping(start));
+        }
+
+        self.regions.insert(region.start, region);
+        Ok(())
+    }
+
+    fn region_containing(&self, address: VirtAddr) -> Option<&UserRegion> {
+        self.regions
+            .range(..=address)
+            .next_back()
+            .map(|(_, region)| region)
+            .filter(|region| region.contains(address))
+    }
+
+    /// Copies `bytes` to the user pages at `start`, whether or not the address space is active
+    /// and the pages are writable.
+    pub fn write(&mut self, start: VirtAddr, bytes: &[u8]) -> Result<(), VmmError> {
+        self.copy(start, bytes.len(), true, |page, range| unsafe {
+            ptr::copy_nonoverlapping(bytes[range.clone()].as_ptr(), page, range.len())
+        })
+    }
+
+    /// Copies `destination.len()` bytes at `start` to `destination`, if they are all part of
+    /// regions.
+    pub fn read(&mut self, start: VirtAddr, destination: &mut [u8]) -> Result<(), VmmError> {
+        self.copy(start, destination.len(), false, |page, range| unsafe {
+            ptr::copy_nonoverlapping(page, destination[range.clone()].as_mut_ptr(), range.len())
+        })
+    }
+
+    /// Resolves a page fault user mode caused at `address`: maps a zeroed frame to a page that
+    /// was not accessed before, or copies a shared frame that is written. Fails if the address
+    /// is not part of a region, or if its region does not allow the access.
+    pub fn handle_page_fault(
+        &mut self,
+        address: VirtAddr,
+        error_code: PageFaultErrorCode,
+    ) -> Result<(), VmmError> {
+        let region = self
+            .region_containing(address)
+            .ok_or(VmmError::NotMapped(address))?;
+
+        let write = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+        let allowed = if write {
+            region.flags.contains(PageTableFlags::WRITABLE)
+        } else if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            !region.flags.contains(PageTableFlags::NO_EXECUTE)
+        } else {
+            true
+        };
+        // present pages allow everything their region allows, except writes while shared
+        let present = error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION);
+        if !allowed || (present && !write) {
+            return Err(VmmError::AccessViolation(address));
+        }
+
+        // a write to a present page faults only while it is shared, or if this CPU still had an
+        // entry from before it was copied. Any other page would fault again forever
+        let page = Page::containing_address(address);
+        let writable = COPY_ON_WRITE | PageTableFlags::WRITABLE;
+        if present && !self.mapped_flags(page).intersects(writable) {
+            return Err(VmmError::AccessViolation(address));
+        }
+
+        self.fault_in(page, write)?;
+        Ok(())
+    }
+
+    /// Creates a copy of the address space that shares all frames mapped so far. Both map them
+    /// read-only, and the first write to one of them gives the writing address space a copy of
+    /// its own.
+    ///
+    /// Only the tests fork until programs can.
+    #[cfg(test)]
+    pub fn fork(&mut self) -> Result<AddressSpace, VmmError> {
+        use alloc::vec::Vec;
+
+        let mut child = AddressSpace::new()?;
+        child.regions = self.regions.clone();
+
+        let mut shared = Vec::new();
+        self.for_each_mapped_page(|page, entry| {
+            let flags = (entry.flags() - PageTableFlags::WRITABLE) | COPY_ON_WRITE;
+            entry.set_flags(flags);
+            shared.push((page, PhysFrame::containing_address(entry.addr()), flags));
+        });
+        // the address space may be the active one
+        tlb::flush_all();
+
+        let mut mapper = child.mapper();
+        for (page, frame, flags) in shared {
+            unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator()) }?.ignore();
+            share_frame(frame);
+        }
+
+        Ok(child)
+    }
+
+    /// The frame of the level 4 page table, which [`activate_level_4_table`] takes.
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Calls `copy` with the kernel address of every part of the `length` bytes at `start` that
+    /// is in one page, and the range of the part. The pages are faulted in as if user mode
+    /// accessed them, for writing if `write` is set.
+    fn copy(
+        &mut self,
+        start: VirtAddr,
+        length: usize,
+        write: bool,
+        mut copy: impl FnMut(*mut u8, Range<usize>),
+    ) -> Result<(), VmmError> {
+        if length == 0 {
+            return Ok(());
+        }
+        user_pages(start, length as u64)?;
+
+        let mut copied = 0;
+
+        while copied < length {
+            let address = start + copied as u64;
+            let frame = self.fault_in(Page::containing_address(address), write)?;
+            let page_offset = address.as_u64() % Size4KiB::SIZE;
+
+            let part = (length - copied).min((Size4KiB::SIZE - page_offset) as usize);
+            copy(
+                physical_to_virtual(frame.start_address() + page_offset).as_mut_ptr(),
+                copied..copied + part,
+            );
+            copied += part;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the frame `page` is mapped to, after mapping it to a zeroed frame if it was not
+    /// mapped yet, or to a copy of its frame if it is shared and going to be written.
+    fn fault_in(&mut self, page: Page, write: bool) -> Result<PhysFrame, VmmError> {
+        let address = page.start_address();
+        let flags = self
+            .region_containing(address)
+            .ok_or(VmmError::NotMapped(address))?
+            .page_flags();
+        let mut mapper = self.mapper();
+
+        let frame = match mapper.translate(address) {
+            TranslateResult::Mapped {
+                frame: MappedFrame::Size4KiB(frame),
+                flags: page_flags,
+                ..
+            } => {
+                if !write || !page_flags.contains(COPY_ON_WRITE) {
+                    return Ok(frame);
+                }
+                frame
+            }
+            TranslateResult::NotMapped => {
+                let frame = allocate_zeroed_frame()?;
+                let result = unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator()) };
+
+                return match result {
+                    Ok(flush) => {
+                        flush.flush();
+                        Ok(frame)
+                    }
+                    Err(error) => {
+                        unsafe { frame_allocator().deallocate_frame(frame) };
+                        Err(error.into())
+                    }
+                };
+            }
+            // user pages are never mapped with huge pages
+            _ => return Err(VmmError::NotMapped(address)),
+        };
+
+        // the last address space that maps a shared frame takes it over
+        let copy = if is_shared(frame) {
+            let copy = allocate_zeroed_frame()?;
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    physical_to_virtual(frame.start_address()).as_ptr::<u8>(),
+                    physical_to_virtual(copy.start_address()).as_mut_ptr::<u8>(),
+                    Size4KiB::SIZE as usize,
+                )
+            };
+            copy
+        } else {
+            frame
+        };
+
+        // the entry is changed in place, so the page stays mapped and nothing can fail anymore
+        self.mapped_entry(page).set_frame(copy, flags);
+        tlb::flush(address);
+        if copy != frame {
+            release_frame(frame);
+        }
+
+        Ok(copy)
+    }
+
+    /// Returns the level 1 page table entry of `page`, which must be mapped.
+    fn mapped_entry(&mut self, page: Page) -> &mut PageTableEntry {
+        let mut table = unsafe { &mut *page_table(self.level_4_frame) };
+        for index in [page.p4_index(), page.p3_index(), page.p2_index()] {
+            let frame = table[index].frame().expect("the page is not mapped");
+            table = unsafe { &mut *page_table(frame) };
+        }
+
+        &mut table[page.p1_index()]
+    }
+
+    /// The flags of the level 1 page table entry of `page`, which are empty if it is not mapped.
+    fn mapped_flags(&mut self, page: Page) -> PageTableFlags {
+        match self.mapper().translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags,
+            _ => PageTableFlags::empty(),
+        }
+    }
+
+    /// Calls `f` with every mapped user page and its level 1 page table entry.
+    fn for_each_mapped_page(&mut self, mut f: impl FnMut(Page, &mut PageTableEntry)) {
+        let table = unsafe { &mut *page_table(self.level_4_frame) };
+
+        for index in USER_LEVEL_4_ENTRIES {
+            let start = VirtAddr::new_truncate((index as u64) << 39);
+            unsafe { visit_pages(&mut table[index], 4, start, &mut f) };
+        }
+    }
+
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let physical_memory_offset = physical_to_virtual(PhysAddr::zero());
+        unsafe {
+            OffsetPageTable::new(&mut *page_table(self.level_4_frame), physical_memory_offset)
+        }
+    }
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        debug_assert_ne!(
+            Cr3::read().0,
+            self.level_4_frame,
+            "the active address space was dropped"
+        );
+
+        self.for_each_mapped_page(|_, entry| {
+            release_frame(PhysFrame::containing_address(entry.addr()))
+        });
+
+        let table = unsafe { &*page_table(self.level_4_frame) };
+        unsafe {
+            for index in USER_LEVEL_4_ENTRIES {
+                free_page_tables(&table[index], 4);
+            }
+            frame_allocator().deallocate_frame(self.level_4_frame);
+        }
+    }
+}
+
+/// Makes the level 4 page table in `frame` the active one on the current CPU, or the one of the
+/// kernel, which maps nothing in user space, for `None`.
+///
+/// ## Safety
+///
+/// Nothing may access the user pages of the address space that was active before anymore, and
+/// the page table must stay alive until another one is activated.
+#[deny(unsafe_op_in_unsafe_fn)]
+pub unsafe fn activate_level_4_table(frame: Option<PhysFrame>) {
+    let frame = frame.unwrap_or_else(|| {
+        *KERNEL_LEVEL_4_FRAME
+            .get()
+            .expect("memory is not initialized")
+    });
+
+    // loading CR3 flushes the TLB, which is only needed if it changes
+    let (active, flags) = Cr3::read();
+    if active != frame {
+        unsafe { Cr3::write(frame, flags) };
+    }
+}
+
+fn allocate_zeroed_frame() -> Result<PhysFrame, VmmError> {
+    let frame = frame_allocator()
+        .allocate_frame()
+        .ok_or(VmmError::Map(MapToError::FrameAllocationFailed))?;
+
+    // the frame may still hold data of the kernel or another program
+    unsafe {
+        physical_to_virtual(frame.start_address())
+            .as_mut_ptr::<u8>()
+            .write_bytes(0, Size4KiB::SIZE as usize)
+    };
+    Ok(frame)
+}
+
+/// Records that one more address space maps `frame`.
+#[cfg(test)]
+fn share_frame(frame: PhysFrame) {
+    *SHARED_FRAMES.lock().entry(frame).or_insert(1) += 1;
+}
+
+fn is_shared(frame: PhysFrame) -> bool {
+    SHARED_FRAMES.lock().contains_key(&frame)
+}
+
+/// Records that an address space no longer maps `frame`, and frees it if no other one does.
+fn release_frame(frame: PhysFrame) {
+    let shared = {
+        let mut shared_frames = SHARED_FRAMES.lock();
+        match shared_frames.get_mut(&frame) {
+            Some(2) => {
+                shared_frames.remove(&frame);
+                true
+            }
+            Some(count) => {
+                *count -= 1;
+                true
+            }
+            None => false,
+        }
+    };
+
+    // the heap may need the frame allocator while the map changes
+    if !shared {
+        unsafe { frame_allocator().deallocate_frame(frame) };
+    }
+}
+
+/// Calls `f` with every present level 1 entry below `entry` of a page table of `level` and the
+/// page it maps, where `start` is the first address `entry` covers.
+///
+/// ## Safety
+///
+/// `entry` must be a user entry of a page table, which maps no huge pages.
+#[deny(unsafe_op_in_unsafe_fn)]
+unsafe fn visit_pages(
+    entry: &mut PageTableEntry,
+    level: u8,
+    start: VirtAddr,
+    f: &mut impl FnMut(Page, &mut PageTableEntry),
+) {
+    if !entry.flags().contains(PageTableFlags::PRESENT) {
+        return;
+    }
+    if level == 1 {
+        f(Page::containing_address(start), entry);
+        return;
+    }
+
+    let table = unsafe { &mut *page_table(PhysFrame::containing_address(entry.addr())) };
+    let entry_size = Size4KiB::SIZE << (9 * (level - 2));
+    for (index, entry) in table.iter_mut().enumerate() {
+        unsafe { visit_pages(entry, level - 1, start + index as u64 * entry_size, f) };
+    }
+}
+
+/// Frees the page tables below `entry` of a page table of `level`, but not the frames of the
+/// pages they map.
+///
+/// ## Safety
+///
+/// Nothing may use the page tables anymore.
+#[deny(unsafe_op_in_unsafe_fn)]
+unsafe fn free_page_tables(entry: &PageTableEntry, level: u8) {
+    if level == 1 || !entry.flags().contains(PageTableFlags::PRESENT) {
+        return;
+    }
+
+    // user pages are never mapped with huge pages
+    debug_assert!(!entry.flags().contains(PageTableFlags::HUGE_PAGE));
+    let frame = PhysFrame::containing_address(entry.addr());
+    let table = unsafe { &*page_table(frame) };
+    for entry in table.iter() {
+        unsafe { free_page_tables(entry, level - 1) };
+    }
+
+    unsafe { frame_allocator().deallocate_frame(frame) };
+}
+
+fn page_table(frame: PhysFrame) -> *mut PageTable {
+    physical_to_virtual(frame.start_address()).as_mut_ptr()
+}
+
+#[test_case]
+fn address_spaces_are_separate() {
+    use super::{USER_SIZE, USER_START};
+    use x86_64::instructions::interrupts;
+
+    let mut first = AddressSpace::new().unwrap();
+    let mut second = AddressSpace::new().unwrap();
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let free_frames = frame_allocator().free_frames();
+
+    first.map(USER_START, Size4KiB::SIZE, flags).unwrap();
+    second.map(USER_START, Size4KiB::SIZE, flags).unwrap();
+    first.write(USER_START + 8u64, &[1, 2, 3]).unwrap();
+    assert!(matches!(
+        second.write(USER_START + Size4KiB::SIZE, &[1]),
+        Err(VmmError::NotMapped(_))
+    ));
+    // reading maps the page as well, which the kernel may not access before
+    second.read(USER_START, &mut [0]).unwrap();
+
+    // the scheduler activates the page table of the kernel when it switches back to this thread
+    let read = |space: &AddressSpace| {
+        interrupts::without_interrupts(|| unsafe {
+            activate_level_4_table(Some(space.level_4_frame()));
+            let bytes = *USER_START.as_ptr::<[u8; 12]>();
+            activate_level_4_table(None);
+            bytes
+        })
+    };
+    assert_eq!(read(&first), [0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 0]);
+    assert_eq!(read(&second), [0; 12]);
+
+    let mut buffer = [1; 16];
+    first
+        .read(USER_START + (Size4KiB::SIZE - 16), &mut buffer)
+        .unwrap();
+    assert_eq!(buffer, [0; 16]);
+    assert!(matches!(
+        first.read(USER_START + (Size4KiB::SIZE - 8), &mut buffer),
+        Err(VmmError::NotMapped(_))
+    ));
+    assert!(matches!(
+        first.map(VirtAddr::new(0x1000), Size4KiB::SIZE, flags),
+        Err(VmmError::NotUserAddress(_))
+    ));
+    assert!(matches!(
+        first.map(USER_START + (USER_SIZE - 8), 16, flags),
+        Err(VmmError::NotUserAddress(_))
+    ));
+    assert!(matches!(
+        first.map(USER_START + 8u64, 16, flags),
+        Err(VmmError::Overlapping(_))
+    ));
+
+    // the page tables of the mappings are freed as well
+    drop(first);
+    drop(second);
+    assert_eq!(frame_allocator().free_frames(), free_frames + 2);
+}
+
+#[test_case]
+fn pages_are_mapped_on_first_access() {
+    use super::USER_START;
+
+    let mut space = AddressSpace::new().unwrap();
+    let code = USER_START;
+    let data = USER_START + Size4KiB::SIZE;
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    space
+        .map(code, Size4KiB::SIZE, PageTableFlags::empty())
+        .unwrap();
+    space.map(data, 2 * Size4KiB::SIZE, flags).unwrap();
+
+    let free_frames = frame_allocator().free_frames();
+    let write = PageFaultErrorCode::USER_MODE | PageFaultErrorCode::CAUSED_BY_WRITE;
+    let fetch = PageFaultErrorCode::USER_MODE | PageFaultErrorCode::INSTRUCTION_FETCH;
+
+    space.handle_page_fault(code, fetch).unwrap();
+    space.handle_page_fault(data + 8u64, write).unwrap();
+    assert!(space.mapper().translate_addr(data).is_some());
+    assert!(space
+        .mapper()
+        .translate_addr(data + Size4KiB::SIZE)
+        .is_none());
+    // two pages and the level 3, 2 and 1 tables above them
+    assert_eq!(frame_allocator().free_frames(), free_frames - 5);
+
+    let present_write = write | PageFaultErrorCode::PROTECTION_VIOLATION;
+    assert!(matches!(
+        space.handle_page_fault(code, present_write),
+        Err(VmmError::AccessViolation(_))
+    ));
+    assert!(matches!(
+        space.handle_page_fault(data + Size4KiB::SIZE, fetch),
+        Err(VmmError::AccessViolation(_))
+    ));
+
+    assert!(matches!(
+        space.handle_page_fault(data + 2 * Size4KiB::SIZE, write),
+        Err(VmmError::NotMapped(_))
+    ));
+
+    // a read-only page that is not shared cannot be fixed by faulting it in
+    let entry = space.mapped_entry(Page::containing_address(data));
+    entry.set_flags(entry.flags() - PageTableFlags::WRITABLE);
+    assert!(matches!(
+        space.handle_page_fault(data, present_write),
+        Err(VmmError::AccessViolation(_))
+    ));
+}
+
+#[test_case]
+fn forks_copy_pages_on_write() {
+    use super::USER_START;
+
+    let free_frames = frame_allocator().free_frames();
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let first_page = USER_START;
+    let second_page = USER_START + Size4KiB::SIZE;
+
+    let mut parent = AddressSpace::new().unwrap();
+    parent.map(first_page, 2 * Size4KiB::SIZE, flags).unwrap();
+    parent.write(first_page, &[1]).unwrap();
+    parent.write(second_page, &[2]).unwrap();
+
+    let mut child = parent.fork().unwrap();
+    let frame = |space: &mut AddressSpace, address| space.mapper().translate_addr(address);
+    assert_eq!(
+        frame(&mut parent, first_page),
+        frame(&mut child, first_page)
+    );
+
+    // reading a shared page leaves it shared, writing copies it
+    let mut byte = [0];
+    child.read(second_page, &mut byte).unwrap();
+    assert_eq!(byte, [2]);
+    child.write(first_page, &[3]).unwrap();
+    parent.read(first_page, &mut byte).unwrap();
+    assert_eq!(byte, [1]);
+    assert_ne!(
+        frame(&mut parent, first_page),
+        frame(&mut child, first_page)
+    );
+
+    // user mode writes are handled the same way, and the last address space that maps a frame
+    // keeps it
+    let write = PageFaultErrorCode::USER_MODE
+        | PageFaultErrorCode::CAUSED_BY_WRITE
+        | PageFaultErrorCode::PROTECTION_VIOLATION;
+    let parent_frame = frame(&mut parent, second_page);
+    child.handle_page_fault(second_page, write).unwrap();
+    parent.handle_page_fault(second_page, write).unwrap();
+    assert_eq!(frame(&mut parent, second_page), parent_frame);
+    assert_ne!(frame(&mut child, second_page), parent_frame);
+
+    drop(child);
+    parent.read(first_page, &mut byte).unwrap();
+    assert_eq!(byte, [1]);
+    drop(parent);
+    assert_eq!(frame_allocator().free_frames(), free_frames);
+}
@@ -0,0 +1,261 @@
+use bootloader_api::info::{MemoryRegionKind, MemoryRegions};
+use core::slice;
+use x86_64::{
+    structures::paging::{
+        frame::PhysFrameRange, FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB,
+        Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// Physical frame allocator keeping one bit per 4 KiB frame (set = in use).
+///
+/// The bitmap itself lives in the first usable region large enough to hold it and is accessed
+/// through the physical memory mapping set up by the bootloader.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    frame_count: usize,
+    free_frames: usize,
+    next_free: usize,
+}
+
+fn usable_ranges(memory_map: &MemoryRegions) -> impl Iterator<Item = (u64, u64)> + '_ {
+    memory_map
+        .iter()
+        .filter(|r| r.kind == MemoryRegionKind::Usable)
+        .map(|r| (align_up(r.start, FRAME_SIZE), r.end & !(FRAME_SIZE - 1)))
+        .filter(|(start, end)| start < end)
+}
+
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
+
+impl BitmapFrameAllocator {
+    /// Creates a frame allocator for all usable regions of the given memory map.
+    ///
+    /// ## Safety
+    ///
+    /// The complete physical memory must be mapped at `physical_memory_offset` and the usable
+    /// regions of `memory_map` must really be unused.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn new(memory_map: &MemoryRegions, physical_memory_offset: VirtAddr) -> Self {
+        let memory_end = usable_ranges(memory_map)
+            .map(|(_, end)| end)
+            .max()
+            .expect("no usable memory regions");
+
+        let frame_count = (memory_end / FRAME_SIZE) as usize;
+        let word_count = frame_count.div_ceil(BITS_PER_WORD);
+        let bitmap_size = align_up((word_count * 8) as u64, FRAME_SIZE);
+
+        let (bitmap_start, _) = usable_ranges(memory_map)
+            .find(|(start, end)| end - start >= bitmap_size)
+            .expect("no usable memory region is large enough for the frame bitmap");
+
+        let bitmap_ptr: *mut u64 = (physical_memory_offset + bitmap_start).as_mut_ptr();
+        let bitmap = unsafe { slice::from_raw_parts_mut(bitmap_ptr, word_count) };
+        bitmap.fill(u64::MAX);
+
+        let mut allocator = Self {
+            bitmap,
+            frame_count,
+            free_frames: 0,
+            next_free: 0,
+        };
+
+        for (start, end) in usable_ranges(memory_map) {
+            allocator.set_range(
+                (start / FRAME_SIZE) as usize,
+                (end / FRAME_SIZE) as usize,
+                false,
+            );
+        }
+
+        let bitmap_first_frame = (bitmap_start / FRAME_SIZE) as usize;
+        let bitmap_frames = (bitmap_size / FRAME_SIZE) as usize;
+        allocator.set_range(bitmap_first_frame, bitmap_first_frame + bitmap_frames, true);
+
+        // never hand out the frame at physical address zero
+        allocator.set_range(0, 1, true);
+
+        allocator
+    }
+
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    pub fn total_frames(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Allocates `count` physically contiguous frames whose first frame is aligned to
+    /// `align` frames.
+    pub fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysFrameRange> {
+        assert!(count > 0 && align.is_power_of_two());
+
+        if count == 1 && align == 1 {
+            let frame = self.allocate_frame()?;
+            return Some(PhysFrame::range(frame, frame + 1));
+        }
+
+        self.allocate_contiguous_in(count, align, self.frame_count)
+    }
+
+    /// Allocates `count` physically contiguous frames that end below `limit`, for hardware that
+    /// can only address low memory.
+    pub fn allocate_below(&mut self, count: usize, limit: PhysAddr) -> Option<PhysFrameRange> {
+        assert!(count > 0);
+
+        let end = ((limit.as_u64() / FRAME_SIZE) as usize).min(self.frame_count);
+        self.allocate_contiguous_in(count, 1, end)
+    }
+
+    /// Allocates the lowest `count` contiguous frames with the given alignment that end at frame
+    /// index `end` or below.
+    fn allocate_contiguous_in(
+        &mut self,
+        count: usize,
+        align: usize,
+        end: usize,
+    ) -> Option<PhysFrameRange> {
+        let mut start = 0;
+        while start + count <= end {
+            match (start..start + count)
+                .rev()
+                .find(|&index| self.is_used(index))
+            {
+                Some(used) => start = (used + 1).next_multiple_of(align),
+                None => {
+                    self.set_range(start, start + count, true);
+                    return Some(PhysFrame::range(
+                        Self::frame_at(start),
+                        Self::frame_at(start + count),
+                    ));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn frame_at(index: usize) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(index as u64 * FRAME_SIZE))
+    }
+
+    fn index_of<S: PageSize>(frame: PhysFrame<S>) -> usize {
+        (frame.start_address().as_u64() / FRAME_SIZE) as usize
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn set_range(&mut self, start: usize, end: usize, used: bool) {
+        for index in start..end.min(self.frame_count) {
+            if self.is_used(index) == used {
+                continue;
+            }
+
+            let word = &mut self.bitmap[index / BITS_PER_WORD];
+            *word ^= 1 << (index % BITS_PER_WORD);
+
+            if used {
+                self.free_frames -= 1;
+            } else {
+                self.free_frames += 1;
+                self.next_free = self.next_free.min(index / BITS_PER_WORD);
+            }
+        }
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let word_index =
+            (self.next_free..self.bitmap.len()).find(|&index| self.bitmap[index] != u64::MAX)?;
+        let index = word_index * BITS_PER_WORD + self.bitmap[word_index].trailing_ones() as usize;
+
+        if index >= self.frame_count {
+            return None;
+        }
+
+        self.next_free = word_index;
+        self.set_range(index, index + 1, true);
+
+        Some(Self::frame_at(index))
+    }
+}
+
+unsafe impl FrameAllocator<Size2MiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let frames_per_page = (Size2MiB::SIZE / FRAME_SIZE) as usize;
+        let range = self.allocate_contiguous(frames_per_page, frames_per_page)?;
+
+        Some(PhysFrame::containing_address(range.start.start_address()))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = Self::index_of(frame);
+        debug_assert!(self.is_used(index), "double free of {frame:?}");
+
+        self.set_range(index, index + 1, false);
+    }
+}
+
+impl FrameDeallocator<Size2MiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let start = Self::index_of(frame);
+        let frames_per_page = (Size2MiB::SIZE / FRAME_SIZE) as usize;
+        debug_assert!((start..start + frames_per_page).all(|index| self.is_used(index)));
+
+        self.set_range(start, start + frames_per_page, false);
+    }
+}
+
+#[test_case]
+fn deallocated_frames_are_reused() {
+    let mut frame_allocator = super::frame_allocator();
+    let free_frames = frame_allocator.free_frames();
+
+    let frame: PhysFrame = frame_allocator.allocate_frame().unwrap();
+    assert_eq!(frame_allocator.free_frames(), free_frames - 1);
+
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    assert_eq!(frame_allocator.free_frames(), free_frames);
+    assert_eq!(frame_allocator.allocate_frame(), Some(frame));
+
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn huge_frames_are_aligned() {
+    let mut frame_allocator = super::frame_allocator();
+    let free_frames = frame_allocator.free_frames();
+
+    let frame: PhysFrame<Size2MiB> = frame_allocator.allocate_frame().unwrap();
+    assert!(frame.start_address().is_aligned(Size2MiB::SIZE));
+    assert_eq!(frame_allocator.free_frames(), free_frames - 512);
+
+    unsafe { frame_allocator.deallocate_frame(frame) };
+    assert_eq!(frame_allocator.free_frames(), free_frames);
+}
+
+#[test_case]
+fn low_frames_are_below_the_limit() {
+    let mut frame_allocator = super::frame_allocator();
+    let limit = PhysAddr::new(0x10_0000);
+
+    let range = frame_allocator.allocate_below(2, limit).unwrap();
+    assert!(range.end.start_address() <= limit);
+
+    for frame in range {
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+}
@@ -0,0 +1,232 @@
+use core::{arch::asm, marker::PhantomData, mem};
+use x86_64::{
+    registers::model_specific::Msr,
+    structures::paging::{
+        mapper::{Translate, TranslateResult},
+        Mapper, Page, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::{mapper, vmm, RegionKind, VmmError};
+
+const IA32_PAT: u32 = 0x277;
+
+/// The power-on default PAT with the last entry changed from uncached to write-combining.
+///
+/// | index | 0  | 1  | 2   | 3  | 4  | 5  | 6   | 7  |
+/// |-------|----|----|-----|----|----|----|-----|----|
+/// | type  | WB | WT | UC- | UC | WB | WT | UC- | WC |
+const PAT_VALUE: u64 = 0x0107_0406_0007_0406;
+
+/// The PAT bit of a 4 KiB page table entry shares its position with the huge page bit.
+const PAT_FLAG: PageTableFlags = PageTableFlags::HUGE_PAGE;
+
+/// The page table flags that select the PAT entry of a page.
+const CACHE_FLAGS: PageTableFlags = PAT_FLAG
+    .union(PageTableFlags::NO_CACHE)
+    .union(PageTableFlags::WRITE_THROUGH);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    Uncached,
+    WriteCombining,
+}
+
+impl CacheMode {
+    /// The page table flags selecting the PAT entry of this mode.
+    fn flags(self) -> PageTableFlags {
+        match self {
+            Self::Uncached => PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH,
+            Self::WriteCombining => {
+                PAT_FLAG | PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH
+            }
+        }
+    }
+}
+
+/// Programs the page attribute table of the current CPU so that every [`CacheMode`] can be
+/// selected by page table flags. Every CPU must do it before it accesses device memory, so that
+/// a mapping has the same memory type on all of them.
+///
+/// Only the last entry changes and no mapping the CPU accessed selects it, so no caches or TLB
+/// entries need to be flushed.
+pub(crate) fn init_pat() {
+    unsafe { Msr::new(IA32_PAT).write(PAT_VALUE) };
+}
+
+/// A physical address range mapped into the kernel address space. It is unmapped when dropped.
+#[derive(Debug)]
+pub struct IoMem {
+    region_start: VirtAddr,
+    base: VirtAddr,
+    size: usize,
+}
+
+/// A typed volatile view of a device register inside an [`IoMem`].
+pub struct Register<'a, T> {
+    address: *mut T,
+    _io_mem: PhantomData<&'a IoMem>,
+}
+
+/// Maps `size` bytes of device memory starting at `physical_address` into the kernel address
+/// space with the given caching attributes.
+///
+/// ## Safety
+///
+/// The range must be device memory (or memory otherwise safe to access with `cache_mode`) that
+/// is not mapped with conflicting caching attributes elsewhere.
+#[deny(unsafe_op_in_unsafe_fn)]
+pub unsafe fn ioremap(
+    physical_address: PhysAddr,
+    size: usize,
+    cache_mode: CacheMode,
+) -> Result<IoMem, VmmError> {
+    let first_frame: PhysFrame = PhysFrame::containing_address(physical_address);
+    let offset = physical_address - first_frame.start_address();
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE | cache_mode.flags();
+
+    let mut vmm = vmm();
+    let region = vmm.reserve(offset + size as u64, RegionKind::Mmio)?;
+
+    if let Err(error) = unsafe { vmm.map_physical(region.start, first_frame, flags) } {
+        vmm.release(region.start)?;
+        return Err(error);
+    }
+
+    Ok(IoMem {
+        region_start: region.start,
+        base: region.start + offset,
+        size,
+    })
+}
+
+/// Changes the caching attributes of memory that is already mapped into the kernel address
+/// space, like the framebuffer mapped by the bootloader.
+///
+/// ## Safety
+///
+/// The range must be mapped with 4 KiB pages, no other CPU may have cached its translations, and
+/// the memory must be safe to access with `cache_mode`.
+#[deny(unsafe_op_in_unsafe_fn)]
+pub unsafe fn set_cache_mode(
+    start: VirtAddr,
+    size: usize,
+    cache_mode: CacheMode,
+) -> Result<(), VmmError> {
+    let mut mapper = mapper();
+    let pages = Page::<Size4KiB>::range_inclusive(
+        Page::containing_address(start),
+        Page::containing_address(start + (size as u64).saturating_sub(1)),
+    );
+
+    for page in pages {
+        let flags = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => flags,
+            _ => return Err(VmmError::NotMapped(page.start_address())),
+        };
+        let flags = (flags - CACHE_FLAGS) | cache_mode.flags();
+
+        unsafe { mapper.update_flags(page, flags)?.flush() };
+    }
+
+    // lines cached with the old mode must not be written back over writes made with the new one
+    unsafe { asm!("wbinvd", options(nostack, preserves_flags)) };
+
+    Ok(())
+}
+
+impl IoMem {
+    /// Returns an accessor for the register of type `T` at `offset` bytes from the start.
+    ///
+    /// Panics if the register is not completely inside the range or is misaligned.
+    pub fn register<T: Copy>(&self, offset: usize) -> Register<'_, T> {
+        assert!(
+            offset + mem::size_of::<T>() <= self.size,
+            "register out of range"
+        );
+
+        let address = self.base + offset;
+        assert!(
+            address.is_aligned(mem::align_of::<T>() as u64),
+            "misaligned register"
+        );
+
+        Register {
+            address: address.as_mut_ptr(),
+            _io_mem: PhantomData,
+        }
+    }
+
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        self.register(offset).read()
+    }
+
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        self.register(offset).write(value);
+    }
+}
+
+impl Drop for IoMem {
+    fn drop(&mut self) {
+        let mut vmm = vmm();
+
+        unsafe { vmm.unmap(self.region_start) }
+            .and_then(|region| vmm.release(region.start))
+            .expect("failed to unmap device memory");
+    }
+}
+
+impl<T: Copy> Register<'_, T> {
+    pub fn read(&self) -> T {
+        unsafe { self.address.read_volatile() }
+    }
+
+    pub fn write(&self, value: T) {
+        unsafe { self.address.write_volatile(value) };
+    }
+
+    pub fn update(&self, f: impl FnOnce(T) -> T) {
+        self.write(f(self.read()));
+    }
+}
+
+#[test_case]
+fn cache_modes_select_their_pat_entries() {
+    let pat_index = |mode: CacheMode| {
+        let flags = mode.flags();
+        flags.contains(PAT_FLAG) as u64 * 4
+            + flags.contains(PageTableFlags::NO_CACHE) as u64 * 2
+            + flags.contains(PageTableFlags::WRITE_THROUGH) as u64
+    };
+    let pat_entry = |mode| (PAT_VALUE >> (pat_index(mode) * 8)) & 0xff;
+
+    assert_eq!(pat_entry(CacheMode::Uncached), 0x00);
+    assert_eq!(pat_entry(CacheMode::WriteCombining), 0x01);
+}
+
+#[test_case]
+fn unmapping_device_memory_releases_its_region() {
+    use super::mapper;
+
+    const IA32_APIC_BASE: u32 = 0x1b;
+    const LOCAL_APIC_VERSION: u64 = 0x30;
+
+    // the interrupt controller maps the local APIC uncached as well
+    let apic_base = unsafe { Msr::new(IA32_APIC_BASE).read() } & 0x000f_ffff_ffff_f000;
+    let physical_address = PhysAddr::new(apic_base + LOCAL_APIC_VERSION);
+    let io_mem = unsafe { ioremap(physical_address, 4, CacheMode::Uncached) }.unwrap();
+    let base = io_mem.base;
+
+    assert_eq!(mapper().translate_addr(base), Some(physical_address));
+    assert_eq!(
+        vmm().region_containing(base).unwrap().kind,
+        RegionKind::Mmio
+    );
+    // integrated local APICs have versions from 0x10 on
+    assert!(io_mem.read::<u32>(0) & 0xff >= 0x10);
+
+    drop(io_mem);
+    assert!(vmm().region_containing(base).is_none());
+    assert!(mapper().translate_addr(base).is_none());
+}
@@ -0,0 +1,120 @@
+mod address_space;
+mod frame_allocator;
+mod mmio;
+mod stack;
+mod user;
+mod vmm;
+
+use bootloader_api::info::MemoryRegions;
+use conquer_once::spin::OnceCell;
+use klib::interrupts::{UninterruptibleMutex, UninterruptibleMutexGuard};
+use x86_64::{
+    registers::control::Cr3,
+    structures::paging::{OffsetPageTable, PageTable},
+    PhysAddr, VirtAddr,
+};
+
+pub use address_space::{activate_level_4_table, AddressSpace};
+pub use frame_allocator::BitmapFrameAllocator;
+pub(crate) use mmio::init_pat;
+pub use mmio::{ioremap, set_cache_mode, CacheMode, IoMem};
+pub use stack::KernelStack;
+pub(crate) use stack::{overflowed_stack, register_boot_stack};
+pub(crate) use user::USER_LEVEL_4_ENTRIES;
+pub use user::{USER_SIZE, USER_START};
+pub use vmm::{RegionKind, VirtualMemoryManager, VmmError};
+
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
+static FRAME_ALLOCATOR: OnceCell<UninterruptibleMutex<BitmapFrameAllocator>> = OnceCell::uninit();
+static MAPPER: OnceCell<UninterruptibleMutex<OffsetPageTable<'static>>> = OnceCell::uninit();
+static VMM: OnceCell<UninterruptibleMutex<VirtualMemoryManager>> = OnceCell::uninit();
+
+/// Returns the address of `address` in the mapping of all physical memory set up by the
+/// bootloader.
+pub(crate) fn physical_to_virtual(address: PhysAddr) -> VirtAddr {
+    let offset = PHYSICAL_MEMORY_OFFSET
+        .get()
+        .expect("physical memory offset is not initialized");
+    *offset + address.as_u64()
+}
+
+pub(crate) fn frame_allocator() -> UninterruptibleMutexGuard<'static, BitmapFrameAllocator> {
+    FRAME_ALLOCATOR
+        .get()
+        .expect("frame allocator is not initialized")
+        .lock()
+}
+
+/// Locks the page table of the kernel.
+///
+/// The heap grows through this mapper, so nothing may be allocated while the guard is held.
+pub(crate) fn mapper() -> UninterruptibleMutexGuard<'static, OffsetPageTable<'static>> {
+    MAPPER.get().expect("mapper is not initialized").lock()
+}
+
+/// Locks the manager of the kernel's virtual address space.
+///
+/// It takes the locks of the mapper and the frame allocator, so neither may be held by the
+/// caller.
+pub(crate) fn vmm() -> UninterruptibleMutexGuard<'static, VirtualMemoryManager> {
+    VMM.get()
+        .expect("virtual memory manager is not initialized")
+        .lock()
+}
+
+/// Locks the virtual memory manager unless it is already locked, e.g. by the code interrupted
+/// by an exception handler.
+pub(crate) fn try_vmm() -> Option<UninterruptibleMutexGuard<'static, VirtualMemoryManager>> {
+    VMM.get()?.try_lock()
+}
+
+#[deny(unsafe_op_in_unsafe_fn)]
+pub unsafe fn init(physical_memory_offset: VirtAddr, memory_regions: &'static MemoryRegions) {
+    PHYSICAL_MEMORY_OFFSET.init_once(|| physical_memory_offset);
+
+    FRAME_ALLOCATOR.init_once(|| {
+        let frame_allocator =
+            unsafe { BitmapFrameAllocator::new(memory_regions, physical_memory_offset) };
+        UninterruptibleMutex::new(frame_allocator)
+    });
+
+    {
+        let frame_allocator = frame_allocator();
+        log::info!(
+            "Frame allocator initialized: {} of {} frames free",
+            frame_allocator.free_frames(),
+            frame_allocator.total_frames()
+        );
+    }
+
+    MAPPER.init_once(|| {
+        let level_4_table = unsafe { active_level_4_table(physical_memory_offset) };
+        assert!(
+            USER_LEVEL_4_ENTRIES
+                .into_iter()
+                .all(|index| level_4_table[index].is_unused()),
+            "the bootloader mapped memory into the address range of user mode"
+        );
+        let mapper = unsafe { OffsetPageTable::new(level_4_table, physical_memory_offset) };
+        UninterruptibleMutex::new(mapper)
+    });
+    address_space::init();
+
+    mmio::init_pat();
+
+    VMM.init_once(|| {
+        let vmm = VirtualMemoryManager::new(mapper().level_4_table(), &mut *frame_allocator());
+        UninterruptibleMutex::new(vmm)
+    });
+}
+
+#[deny(unsafe_op_in_unsafe_fn)]
+unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let phys = level_4_table_frame.start_address();
+    let virt = physical_memory_offset + phys.as_u64();
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    unsafe { &mut *page_table_ptr }
+}
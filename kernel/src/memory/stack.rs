@@ -0,0 +1,98 @@
+use core::arch::asm;
+use x86_64::{
+    structures::paging::{mapper::Translate, Page, PageSize, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+use super::{mapper, try_vmm, vmm, vmm::VirtualRegion, RegionKind, VmmError};
+
+/// Size of the unmapped area below every kernel stack.
+const GUARD_SIZE: u64 = Size4KiB::SIZE;
+
+/// A kernel stack with an unmapped guard page below it, so that an overflow causes a page fault
+/// instead of silently overwriting other memory. It is unmapped when dropped.
+#[derive(Debug)]
+pub struct KernelStack {
+    region: VirtualRegion,
+}
+
+impl KernelStack {
+    /// Allocates a stack of `size` bytes (rounded up to whole pages). The name is reported when
+    /// the stack overflows.
+    pub fn new(name: &'static str, size: u64) -> Result<Self, VmmError> {
+        let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+        let region = vmm().allocate(size, GUARD_SIZE, RegionKind::Stack(name), flags)?;
+
+        Ok(Self { region })
+    }
+
+    /// The initial stack pointer. The stack grows down from here.
+    pub fn top(&self) -> VirtAddr {
+        self.region.end()
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let mut vmm = vmm();
+
+        unsafe { vmm.unmap(self.region.start) }
+            .and_then(|region| vmm.release(region.start))
+            .expect("failed to unmap kernel stack");
+    }
+}
+
+/// Records the stack the bootloader set up for the kernel, so that its guard page is recognized.
+///
+/// Must be called on the boot stack. `size` is the configured kernel stack size.
+pub(crate) fn register_boot_stack(size: u64) -> Result<(), VmmError> {
+    let stack_pointer: u64;
+    unsafe {
+        asm!("mov {}, rsp", out(reg) stack_pointer, options(nomem, nostack, preserves_flags))
+    };
+
+    // the bootloader maps the stack directly above its guard page, which is the first unmapped
+    // page below the stack pointer
+    let mut guard_page = Page::<Size4KiB>::containing_address(VirtAddr::new(stack_pointer));
+    {
+        let mapper = mapper();
+        while mapper.translate_addr(guard_page.start_address()).is_some() {
+            guard_page -= 1;
+        }
+    }
+
+    vmm().track(
+        guard_page.start_address(),
+        GUARD_SIZE + size.next_multiple_of(Size4KiB::SIZE),
+        GUARD_SIZE,
+        RegionKind::Stack("boot"),
+    )?;
+
+    Ok(())
+}
+
+/// Returns the name of the kernel stack whose guard page contains `address`.
+///
+/// Returns `None` if the virtual memory manager is locked, so that a fault while it is held
+/// does not deadlock.
+pub(crate) fn overflowed_stack(address: VirtAddr) -> Option<&'static str> {
+    let vmm = try_vmm()?;
+    let region = vmm.region_containing(address)?;
+
+    match region.kind {
+        RegionKind::Stack(name) if region.guard_contains(address) => Some(name),
+        _ => None,
+    }
+}
+
+#[test_case]
+fn stacks_are_guarded() {
+    let stack = KernelStack::new("test", 2 * Size4KiB::SIZE).unwrap();
+    let bottom = stack.top() - 2 * Size4KiB::SIZE;
+
+    let ptr: *mut u64 = bottom.as_mut_ptr();
+    unsafe { ptr.write_volatile(42) };
+    assert_eq!(overflowed_stack(bottom), None);
+    assert_eq!(overflowed_stack(bottom - 8u64), Some("test"));
+    assert_eq!(overflowed_stack(VirtAddr::new(0)), None);
+}
@@ -0,0 +1,36 @@
+use core::ops::Range;
+use x86_64::{
+    structures::paging::{page::PageRange, Page, PageSize, Size4KiB},
+    VirtAddr,
+};
+
+use super::VmmError;
+
+/// The level 4 page table entries that cover the addresses of user mode. The kernel maps nothing
+/// there.
+pub(crate) const USER_LEVEL_4_ENTRIES: Range<usize> = 128..256;
+
+/// Start of the addresses user mode can use.
+pub const USER_START: VirtAddr = VirtAddr::new_truncate(0x0000_4000_0000_0000);
+/// Size of the address range of user mode, which ends one page before the lower half. The last
+/// page stays unmapped: `sysretq` faults in the kernel if it returns to the non-canonical address
+/// after a system call instruction at its very end.
+pub const USER_SIZE: u64 = 0x0000_4000_0000_0000 - Size4KiB::SIZE;
+
+/// The pages covering `size` bytes at `start`, if they are all part of the user address range.
+pub(super) fn user_pages(start: VirtAddr, size: u64) -> Result<PageRange, VmmError> {
+    let user_end = USER_START.as_u64() + USER_SIZE;
+    let in_range = start >= USER_START
+        && size > 0
+        && start
+            .as_u64()
+            .checked_add(size)
+            .is_some_and(|end| end <= user_end);
+    if !in_range {
+        return Err(VmmError::NotUserAddress(start));
+    }
+
+    let first = Page::<Size4KiB>::containing_address(start);
+    let last = Page::containing_address(start + (size - 1));
+    Ok(Page::range(first, last + 1))
+}
@@ -0,0 +1,473 @@
+use core::fmt;
+use x86_64::{
+    structures::paging::{
+        mapper::{FlagUpdateError, MapToError, UnmapError},
+        page::PageRange,
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageSize, PageTable, PageTableFlags,
+        PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::{frame_allocator, mapper, physical_to_virtual, USER_LEVEL_4_ENTRIES};
+
+/// Maximum number of regions the kernel address space can track. The list has a fixed size so
+/// that reserving the heap does not depend on the heap.
+const MAX_REGIONS: usize = 256;
+
+/// Size of the address range covered by one level 4 page table entry.
+const LEVEL_4_ENTRY_SIZE: u64 = 512 * 512 * 512 * Size4KiB::SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Heap,
+    /// A kernel stack with the given name.
+    Stack(&'static str),
+    Mmio,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct VirtualRegion {
+    pub start: VirtAddr,
+    pub size: u64,
+    pub kind: RegionKind,
+    /// Bytes at the start of the region that are never mapped, so that running off the bottom
+    /// of a stack faults instead of corrupting the memory below it.
+    pub guard_size: u64,
+    /// Flags the region was mapped with by [`VirtualMemoryManager::map`], or `None` if it is
+    /// only reserved or mapped by its owner (as the heap is, while it grows).
+    pub flags: Option<PageTableFlags>,
+    /// Start of the physical range a region mapped by [`VirtualMemoryManager::map_physical`]
+    /// refers to. The frames of all other mapped regions belong to the region.
+    pub physical_start: Option<PhysAddr>,
+}
+
+#[derive(Debug)]
+pub enum VmmError {
+    OutOfVirtualSpace,
+    TooManyRegions,
+    NoSuchRegion(VirtAddr),
+    AlreadyMapped(VirtAddr),
+    NotMapped(VirtAddr),
+    Overlapping(VirtAddr),
+    /// The range starting at the address is not part of the address range of user mode.
+    NotUserAddress(VirtAddr),
+    /// User mode accessed the address in a way its region does not allow.
+    AccessViolation(VirtAddr),
+    Map(MapToError<Size4KiB>),
+    Unmap(UnmapError),
+    FlagUpdate(FlagUpdateError),
+}
+
+impl fmt::Display for VmmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfVirtualSpace => write!(f, "out of kernel address space"),
+            Self::TooManyRegions => write!(f, "too many regions"),
+            Self::NoSuchRegion(start) => write!(f, "no region starts at {start:?}"),
+            Self::AlreadyMapped(start) => write!(f, "region at {start:?} is already mapped"),
+            Self::NotMapped(start) => write!(f, "region at {start:?} is not mapped"),
+            Self::Overlapping(start) => write!(f, "region at {start:?} overlaps another region"),
+            Self::NotUserAddress(start) => {
+                write!(f, "range at {start:?} is outside of user space")
+            }
+            Self::AccessViolation(address) => {
+                write!(f, "access at {address:?} is not allowed by its region")
+            }
+            Self::Map(error) => write!(f, "mapping failed: {error:?}"),
+            Self::Unmap(error) => write!(f, "unmapping failed: {error:?}"),
+            Self::FlagUpdate(error) => write!(f, "updating flags failed: {error:?}"),
+        }
+    }
+}
+
+impl From<MapToError<Size4KiB>> for VmmError {
+    fn from(error: MapToError<Size4KiB>) -> Self {
+        Self::Map(error)
+    }
+}
+
+impl From<UnmapError> for VmmError {
+    fn from(error: UnmapError) -> Self {
+        Self::Unmap(error)
+    }
+}
+
+impl From<FlagUpdateError> for VmmError {
+    fn from(error: FlagUpdateError) -> Self {
+        Self::FlagUpdate(error)
+    }
+}
+
+impl VirtualRegion {
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    pub fn contains(&self, address: VirtAddr) -> bool {
+        self.start <= address && address < self.end()
+    }
+
+    /// Whether `address` is inside the guard area of the region.
+    pub fn guard_contains(&self, address: VirtAddr) -> bool {
+        self.start <= address && address < self.start + self.guard_size
+    }
+
+    /// The pages of the region that can be mapped, i.e. all pages above the guard area.
+    pub fn pages(&self) -> PageRange {
+        let start = Page::containing_address(self.start + self.guard_size);
+        Page::range(
+            start,
+            start + (self.size - self.guard_size) / Size4KiB::SIZE,
+        )
+    }
+}
+
+/// Hands out and tracks the virtual memory regions of the kernel inside one otherwise unused
+/// level 4 entry of the kernel page table.
+pub struct VirtualMemoryManager {
+    start: VirtAddr,
+    end: VirtAddr,
+    regions: [Option<VirtualRegion>; MAX_REGIONS],
+    region_count: usize,
+}
+
+impl VirtualMemoryManager {
+    /// Creates a manager for the address range of the first unused level 4 entry of
+    /// `level_4_table`, preferring the higher half. The entries of user mode are never used.
+    ///
+    /// The level 3 table of the entry is allocated right away, so that the level 4 entries of the
+    /// kernel never change afterwards and the address spaces of programs can copy them.
+    pub fn new(
+        level_4_table: &mut PageTable,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Self {
+        let index = (256..512)
+            .chain(1..USER_LEVEL_4_ENTRIES.start)
+            .find(|&index| level_4_table[index].is_unused())
+            .expect("no unused level 4 entry for kernel mappings");
+
+        let level_3_frame = frame_allocator
+            .allocate_frame()
+            .expect("failed to allocate the level 3 table for kernel mappings");
+        unsafe {
+            physical_to_virtual(level_3_frame.start_address())
+                .as_mut_ptr::<PageTable>()
+                .write(PageTable::new())
+        };
+        level_4_table[index].set_frame(
+            level_3_frame,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+        );
+
+        let start = VirtAddr::new_truncate(index as u64 * LEVEL_4_ENTRY_SIZE);
+
+        Self {
+            start,
+            end: start + (LEVEL_4_ENTRY_SIZE - 1),
+            regions: [None; MAX_REGIONS],
+            region_count: 0,
+        }
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = &VirtualRegion> {
+        self.regions[..self.region_count].iter().flatten()
+    }
+
+    pub fn region_containing(&self, address: VirtAddr) -> Option<&VirtualRegion> {
+        self.regions().find(|region| region.contains(address))
+    }
+
+    /// Reserves `size` bytes (rounded up to whole pages) of address space without mapping
+    /// anything.
+    pub fn reserve(&mut self, size: u64, kind: RegionKind) -> Result<VirtualRegion, VmmError> {
+        self.reserve_guarded(size, 0, kind)
+    }
+
+    /// Reserves `size` bytes of address space directly above `guard_size` bytes that are never
+    /// mapped. Both sizes are rounded up to whole pages.
+    pub fn reserve_guarded(
+        &mut self,
+        size: u64,
+        guard_size: u64,
+        kind: RegionKind,
+    ) -> Result<VirtualRegion, VmmError> {
+        let guard_size = guard_size.next_multiple_of(Size4KiB::SIZE);
+        let size = guard_size + size.next_multiple_of(Size4KiB::SIZE);
+        let mut start = self.start;
+
+        // tracked regions outside of the managed range are skipped
+        for region in self.regions() {
+            if region.end() <= start {
+                continue;
+            }
+            if region.start - start >= size {
+                break;
+            }
+            start = region.end();
+        }
+
+        if start > self.end || self.end - start < size - 1 {
+            return Err(VmmError::OutOfVirtualSpace);
+        }
+
+        self.insert(VirtualRegion {
+            start,
+            size,
+            kind,
+            guard_size,
+            flags: None,
+            physical_start: None,
+        })
+    }
+
+    /// Records a range that was mapped outside of the manager, e.g. by the bootloader, so that
+    /// it can be looked up. The range may lie outside of the managed range and must not be
+    /// mapped, unmapped or released through the manager.
+    pub fn track(
+        &mut self,
+        start: VirtAddr,
+        size: u64,
+        guard_size: u64,
+        kind: RegionKind,
+    ) -> Result<VirtualRegion, VmmError> {
+        self.insert(VirtualRegion {
+            start,
+            size,
+            kind,
+            guard_size,
+            flags: None,
+            physical_start: None,
+        })
+    }
+
+    /// Reserves a region of `size` bytes above `guard_size` unmapped bytes, like
+    /// [`Self::reserve_guarded`], and backs it with newly allocated frames.
+    pub fn allocate(
+        &mut self,
+        size: u64,
+        guard_size: u64,
+        kind: RegionKind,
+        flags: PageTableFlags,
+    ) -> Result<VirtualRegion, VmmError> {
+        let region = self.reserve_guarded(size, guard_size, kind)?;
+
+        match self.map(region.start, flags) {
+            Ok(region) => Ok(region),
+            Err(error) => {
+                self.release(region.start)?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Backs the reserved region starting at `start` with newly allocated frames.
+    pub fn map(
+        &mut self,
+        start: VirtAddr,
+        flags: PageTableFlags,
+    ) -> Result<VirtualRegion, VmmError> {
+        let region = self.region_mut(start)?;
+        if region.flags.is_some() {
+            return Err(VmmError::AlreadyMapped(start));
+        }
+
+        let flags = flags | PageTableFlags::PRESENT;
+        let mut mapper = mapper();
+        let mut frame_allocator = frame_allocator();
+
+        for page in region.pages() {
+            let result = frame_allocator
+                .allocate_frame()
+                .ok_or(MapToError::FrameAllocationFailed)
+                .and_then(|frame| unsafe {
+                    mapper.map_to(page, frame, flags, &mut *frame_allocator)
+                });
+
+            match result {
+                Ok(flush) => flush.flush(),
+                Err(error) => {
+                    let pages = Page::range(region.pages().start, page);
+                    unsafe { unmap_pages(pages, &mut *mapper, &mut *frame_allocator)? };
+                    return Err(error.into());
+                }
+            }
+        }
+
+        region.flags = Some(flags);
+        Ok(*region)
+    }
+
+    /// Maps the reserved region starting at `start` to the physical range starting at the frame
+    /// `physical_start`.
+    ///
+    /// ## Safety
+    ///
+    /// The physical range must be safe to access with the given flags, e.g. device memory that
+    /// is not used by another mapping with different caching attributes.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn map_physical(
+        &mut self,
+        start: VirtAddr,
+        physical_start: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<VirtualRegion, VmmError> {
+        let region = self.region_mut(start)?;
+        if region.flags.is_some() {
+            return Err(VmmError::AlreadyMapped(start));
+        }
+
+        let flags = flags | PageTableFlags::PRESENT;
+        let mut mapper = mapper();
+        let mut frame_allocator = frame_allocator();
+
+        for (index, page) in region.pages().enumerate() {
+            let frame = physical_start + index as u64;
+            let result = unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator) };
+
+            match result {
+                Ok(flush) => flush.flush(),
+                Err(error) => {
+                    for page in Page::range(region.pages().start, page) {
+                        mapper.unmap(page)?.1.flush();
+                    }
+                    return Err(error.into());
+                }
+            }
+        }
+
+        region.flags = Some(flags);
+        region.physical_start = Some(physical_start.start_address());
+        Ok(*region)
+    }
+
+    /// Unmaps the region starting at `start` and returns the frames it owns to the frame
+    /// allocator. The address range stays reserved.
+    ///
+    /// ## Safety
+    ///
+    /// Nothing may access the region anymore.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn unmap(&mut self, start: VirtAddr) -> Result<VirtualRegion, VmmError> {
+        let region = self.region_mut(start)?;
+        if region.flags.is_none() {
+            return Err(VmmError::NotMapped(start));
+        }
+
+        let mut mapper = mapper();
+        if region.physical_start.is_some() {
+            for page in region.pages() {
+                mapper.unmap(page)?.1.flush();
+            }
+        } else {
+            unsafe { unmap_pages(region.pages(), &mut *mapper, &mut *frame_allocator())? };
+        }
+
+        region.flags = None;
+        region.physical_start = None;
+        Ok(*region)
+    }
+
+    /// Gives the address range of an unmapped region back.
+    pub fn release(&mut self, start: VirtAddr) -> Result<VirtualRegion, VmmError> {
+        let position = self.position(start)?;
+        let region = self.regions[position].unwrap();
+
+        if region.flags.is_some() {
+            return Err(VmmError::AlreadyMapped(start));
+        }
+
+        self.regions
+            .copy_within(position + 1..self.region_count, position);
+        self.region_count -= 1;
+        self.regions[self.region_count] = None;
+
+        Ok(region)
+    }
+
+    /// Adds `region` to the sorted region list.
+    fn insert(&mut self, region: VirtualRegion) -> Result<VirtualRegion, VmmError> {
+        if self.region_count == MAX_REGIONS {
+            return Err(VmmError::TooManyRegions);
+        }
+
+        let position = self
+            .regions()
+            .position(|other| other.start >= region.start)
+            .unwrap_or(self.region_count);
+
+        let overlaps_previous = position > 0
+            && self.regions[position - 1].is_some_and(|previous| previous.end() > region.start);
+        let overlaps_next = position < self.region_count
+            && self.regions[position].is_some_and(|next| next.start < region.end());
+        if overlaps_previous || overlaps_next {
+            return Err(VmmError::Overlapping(region.start));
+        }
+
+        self.regions
+            .copy_within(position..self.region_count, position + 1);
+        self.regions[position] = Some(region);
+        self.region_count += 1;
+
+        Ok(region)
+    }
+
+    fn position(&self, start: VirtAddr) -> Result<usize, VmmError> {
+        self.regions[..self.region_count]
+            .iter()
+            .position(|region| region.is_some_and(|region| region.start == start))
+            .ok_or(VmmError::NoSuchRegion(start))
+    }
+
+    fn region_mut(&mut self, start: VirtAddr) -> Result<&mut VirtualRegion, VmmError> {
+        let position = self.position(start)?;
+        Ok(self.regions[position].as_mut().unwrap())
+    }
+}
+
+#[deny(unsafe_op_in_unsafe_fn)]
+pub(super) unsafe fn unmap_pages(
+    pages: impl Iterator<Item = Page>,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+) -> Result<(), UnmapError> {
+    for page in pages {
+        let (frame, flush): (PhysFrame, _) = mapper.unmap(page)?;
+        flush.flush();
+        unsafe { frame_deallocator.deallocate_frame(frame) };
+    }
+
+    Ok(())
+}
+
+#[test_case]
+fn regions_can_be_mapped_and_released() {
+    use x86_64::structures::paging::mapper::{Translate, TranslateResult};
+
+    let mut vmm = super::vmm();
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    let region = vmm
+        .allocate(3 * Size4KiB::SIZE, 0, RegionKind::Stack("test"), flags)
+        .unwrap();
+    let ptr: *mut u64 = (region.end() - 8u64).as_mut_ptr();
+    unsafe { ptr.write_volatile(42) };
+    assert_eq!(
+        vmm.region_containing(region.start).unwrap().kind,
+        RegionKind::Stack("test")
+    );
+
+    assert_eq!(region.flags, Some(flags | PageTableFlags::PRESENT));
+
+    for page in region.pages() {
+        let TranslateResult::Mapped { flags, .. } = mapper().translate(page.start_address()) else {
+            panic!("{page:?} of an allocated region is not mapped");
+        };
+        assert!(flags.contains(PageTableFlags::PRESENT | PageTableFlags::WRITABLE));
+        assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+    }
+
+    unsafe { vmm.unmap(region.start) }.unwrap();
+    vmm.release(region.start).unwrap();
+    assert!(vmm.region_containing(region.start).is_none());
+}
@@ -0,0 +1,142 @@
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use klib::interrupts::{UninterruptibleMutex, UninterruptibleMutexGuard};
+use x86_64::{structures::paging::PhysFrame, VirtAddr};
+
+use crate::{
+    memory::{AddressSpace, VmmError},
+    thread::{self, JoinHandle},
+    user::{self, ElfError, ExitStatus, Program},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProcessId(u64);
+
+impl ProcessId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        ProcessId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for ProcessId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A program with an address space of its own, which its threads run in. Its user mappings and
+/// their frames are freed when the last thread of the process exited and nothing else refers to
+/// it.
+pub struct Process {
+    id: ProcessId,
+    name: &'static str,
+    /// The level 4 page table of the address space, which the scheduler activates without
+    /// locking the address space.
+    level_4_frame: PhysFrame,
+    address_space: UninterruptibleMutex<AddressSpace>,
+}
+
+/// Loads the ELF executable `image` into a new process and starts it on a thread of the process
+/// with `arguments` and `environment`. The handle returns how the program ended.
+pub fn spawn(
+    name: &'static str,
+    image: &[u8],
+    arguments: &[&str],
+    environment: &[&str],
+) -> Result<JoinHandle<ExitStatus>, ElfError> {
+    let mut address_space = AddressSpace::new().map_err(ElfError::Memory)?;
+    let program = Program::load(&mut address_space, image, arguments, environment)?;
+
+    Process::new(name, address_space)
+        .spawn_thread(program.entry, program.stack_pointer)
+        .map_err(ElfError::Memory)
+}
+
+/// The process the running thread belongs to, or `None` for kernel threads.
+pub fn current() -> Option<Arc<Process>> {
+    thread::current_process()
+}
+
+impl Process {
+    pub fn new(name: &'static str, address_space: AddressSpace) -> Arc<Self> {
+        let process = Arc::new(Self {
+            id: ProcessId::new(),
+            name,
+            level_4_frame: address_space.level_4_frame(),
+            address_space: UninterruptibleMutex::new(address_space),
+        });
+
+        log::debug!("Created process {} ({name})", process.id);
+        process
+    }
+
+    pub fn id(&self) -> ProcessId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn address_space(&self) -> UninterruptibleMutexGuard<'_, AddressSpace> {
+        self.address_space.lock()
+    }
+
+    pub(crate) fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Starts a thread of the process that runs in user mode at `entry` with the stack pointer
+    /// `stack_pointer`, until the program exits or is killed.
+    pub fn spawn_thread(
+        self: &Arc<Self>,
+        entry: VirtAddr,
+        stack_pointer: VirtAddr,
+    ) -> Result<JoinHandle<ExitStatus>, VmmError> {
+        // the scheduler activates the address space of the process before the thread runs, and
+        // a program that starts at an address it cannot execute is killed
+        thread::spawn_in_process(self.name, self.clone(), move || unsafe {
+            user::enter(entry, stack_pointer)
+        })
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        log::debug!("Process {} ({}) ended", self.id, self.name);
+    }
+}
+
+#[test_case]
+fn programs_run_in_processes() {
+    // the program exits with 10 * argc + envc
+    let handle = spawn("hello", user::HELLO, &["hello", "world"], &["KEY=value"]).unwrap();
+    assert_eq!(handle.join(), ExitStatus::Exited(21));
+}
+
+#[test_case]
+fn segment_permissions_are_enforced() {
+    for violation in ["write-text", "run-rodata"] {
+        let handle = spawn("hello", user::HELLO, &["hello", violation], &[]).unwrap();
+        assert_eq!(handle.join(), ExitStatus::Killed);
+    }
+}
+
+#[test_case]
+fn exited_processes_are_dropped() {
+    let mut address_space = AddressSpace::new().unwrap();
+    let program = Program::load(&mut address_space, user::HELLO, &["hello"], &[]).unwrap();
+    let process = Process::new("hello", address_space);
+    let handle = process
+        .spawn_thread(program.entry, program.stack_pointer)
+        .unwrap();
+
+    // the thread holds the last reference, which is dropped after it switched away for good
+    let process = Arc::downgrade(&process);
+    assert_eq!(handle.join(), ExitStatus::Exited(10));
+    assert!(process.upgrade().is_none());
+}
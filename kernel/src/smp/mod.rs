@@ -0,0 +1,210 @@
+mod trampoline;
+
+use alloc::boxed::Box;
+use core::{
+    fmt, mem,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use klib::percpu::{self, PerCpu};
+use x86_64::structures::paging::{mapper::MapToError, Size4KiB};
+
+use crate::{
+    acpi, gdt,
+    interrupts::{self, controller, LocalApic},
+    memory::{self, KernelStack, VmmError},
+    syscall, thread,
+    time::Instant,
+};
+use trampoline::Trampoline;
+
+/// Size of the stack an AP runs on after it started.
+const AP_STACK_SIZE: u64 = 64 * 1024;
+
+/// Time an AP has to start running kernel code after the startup IPI.
+const STARTUP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Number of CPUs running kernel code, including the BSP.
+static ONLINE_CPUS: AtomicUsize = AtomicUsize::new(1);
+
+/// Set by an AP when it no longer needs the trampoline.
+static AP_STARTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum SmpError {
+    /// No free memory is left where the trampoline and its page table have to be.
+    NoLowMemory,
+    Map(MapToError<Size4KiB>),
+    Stack(VmmError),
+    /// The CPU did not start running kernel code.
+    Timeout,
+}
+
+impl fmt::Display for SmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoLowMemory => write!(f, "no free low memory for the trampoline"),
+            Self::Map(error) => write!(f, "failed to map the trampoline: {error:?}"),
+            Self::Stack(error) => write!(f, "failed to allocate a stack: {error}"),
+            Self::Timeout => write!(f, "the CPU did not respond"),
+        }
+    }
+}
+
+/// Installs the per-CPU data of the BSP, which is CPU 0. Nothing may use [`percpu::current`]
+/// before.
+pub(crate) fn init_bsp() {
+    install_per_cpu(0);
+}
+
+/// Starts the application processors listed in the MADT. They initialize their own descriptor
+/// tables and local APIC, and wait in an idle loop.
+pub(crate) fn init() {
+    let Some(local_apic) = controller().local_apic() else {
+        log::info!("No local APIC, only the BSP is used");
+        return;
+    };
+    let Some(madt) = acpi::tables().and_then(|tables| tables.madt.as_ref()) else {
+        log::info!("No MADT, only the BSP is used");
+        return;
+    };
+
+    let mut trampoline = match Trampoline::new() {
+        Ok(trampoline) => trampoline,
+        Err(error) => {
+            log::warn!("Cannot start the application processors: {error}");
+            return;
+        }
+    };
+
+    let bsp_id = local_apic.id() as u32;
+    let application_processors = madt
+        .processors
+        .iter()
+        .filter(|processor| processor.enabled && processor.local_apic_id != bsp_id);
+
+    for processor in application_processors {
+        // the interrupt command register only addresses 8 bit APIC IDs
+        let Ok(apic_id) = u8::try_from(processor.local_apic_id) else {
+            log::warn!(
+                "Skipping CPU with APIC ID {}, x2APIC mode is not supported",
+                processor.local_apic_id
+            );
+            continue;
+        };
+
+        let index = ONLINE_CPUS.load(Ordering::Relaxed);
+        if let Err(error) = start(local_apic, &mut trampoline, apic_id, index) {
+            log::warn!("Failed to start the CPU with APIC ID {apic_id}: {error}");
+        }
+    }
+
+    log::info!("{} CPUs online", cpu_count());
+}
+
+/// Starts the AP with the local APIC ID `apic_id` with INIT-SIPI-SIPI and waits until it runs
+/// kernel code.
+fn start(
+    local_apic: &LocalApic,
+    trampoline: &mut Trampoline,
+    apic_id: u8,
+    index: usize,
+) -> Result<(), SmpError> {
+    let stack = KernelStack::new("ap boot", AP_STACK_SIZE).map_err(SmpError::Stack)?;
+    trampoline.prepare(ap_main, index, stack.top());
+    AP_STARTED.store(false, Ordering::Release);
+
+    local_apic.send_init(apic_id);
+    delay(Duration::from_millis(10));
+
+    // a second startup IPI is only needed if the CPU missed the first one
+    for _ in 0..2 {
+        local_apic.send_startup(apic_id, trampoline.vector());
+
+        let start = Instant::now();
+        while start.elapsed() < STARTUP_TIMEOUT {
+            if AP_STARTED.load(Ordering::Acquire) {
+                // the AP runs on the stack for as long as it runs
+                mem::forget(stack);
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    // the CPU must not run the trampoline later, when it is prepared for another one
+    local_apic.send_init(apic_id);
+    Err(SmpError::Timeout)
+}
+
+fn delay(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        core::hint::spin_loop();
+    }
+}
+
+/// The first Rust code an AP runs, on the stack the BSP allocated for it.
+extern "C" fn ap_main(index: usize) -> ! {
+    // the page table of the trampoline is freed once all APs are started
+    unsafe { memory::activate_level_4_table(None) };
+    install_per_cpu(index);
+    memory::init_pat();
+    gdt::init();
+    syscall::init();
+    interrupts::init_ap();
+    thread::init_ap();
+
+    ONLINE_CPUS.fetch_add(1, Ordering::Relaxed);
+    AP_STARTED.store(true, Ordering::Release);
+    log::info!("CPU {} is online", percpu::current().id());
+
+    // nothing is scheduled on the APs yet, they only wake up for interrupts sent to them
+    x86_64::instructions::interrupts::enable();
+    thread::idle();
+}
+
+/// Gives the current CPU per-CPU data of its own, which is never freed.
+fn install_per_cpu(id: usize) {
+    let per_cpu: &'static PerCpu = Box::leak(Box::new(PerCpu::new(id)));
+    per_cpu.install();
+}
+
+/// Number of CPUs running kernel code.
+pub fn cpu_count() -> usize {
+    ONLINE_CPUS.load(Ordering::Relaxed)
+}
+
+#[test_case]
+fn application_processors_are_started() {
+    let Some(madt) = acpi::tables().and_then(|tables| tables.madt.as_ref()) else {
+        return;
+    };
+    if controller().local_apic().is_none() {
+        return;
+    }
+
+    let enabled = madt
+        .processors
+        .iter()
+        .filter(|processor| processor.enabled)
+        .count();
+    assert_eq!(cpu_count(), enabled);
+
+    let idle_threads = thread::threads()
+        .into_iter()
+        .filter(|thread| thread.priority == thread::Priority::Idle)
+        .count();
+    assert_eq!(idle_threads, enabled);
+}
+
+#[test_case]
+fn per_cpu_data_is_installed() {
+    let per_cpu = percpu::current();
+    assert_eq!(per_cpu.id(), 0);
+    assert!(per_cpu.current_thread().is_some());
+
+    let counters = per_cpu.counters();
+    thread::spawn("test", || {}).unwrap().join();
+    assert!(per_cpu.counters().context_switches > counters.context_switches);
+}
@@ -0,0 +1,301 @@
+use core::{arch::global_asm, mem, ptr, slice};
+use x86_64::{
+    registers::{
+        control::{Cr0, Cr3, Cr4, Cr4Flags},
+        model_specific::{Efer, EferFlags},
+    },
+    structures::paging::{
+        mapper::MapToError,
+        page_table::{FrameError, PageTableEntry},
+        FrameAllocator, FrameDeallocator, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+use super::SmpError;
+use crate::memory::{frame_allocator, physical_to_virtual};
+
+/// APs start in real mode, so the trampoline has to be in the first megabyte.
+const TRAMPOLINE_LIMIT: PhysAddr = PhysAddr::new(0x10_0000);
+/// The trampoline loads CR3 before it enters long mode, which only takes 32 bit addresses.
+const PAGE_TABLE_LIMIT: PhysAddr = PhysAddr::new(0x1_0000_0000);
+
+const CODE_32_SELECTOR: u16 = 0x08;
+const DATA_SELECTOR: u16 = 0x10;
+const CODE_64_SELECTOR: u16 = 0x18;
+
+/// The part of the trampoline the BSP fills in before it starts an AP.
+#[repr(C)]
+struct TrampolineData {
+    cr0: u64,
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    stack_top: u64,
+    entry: u64,
+    argument: u64,
+}
+
+// The AP starts at offset 0 of the trampoline page in real mode, with CS set to the page. The
+// code only uses addresses relative to the start, since it is copied to a page chosen at runtime.
+// It switches to protected mode, enables paging with the page tables of the kernel to enter long
+// mode and calls the entry point on the stack the BSP allocated.
+global_asm!(
+    ".pushsection .rodata.ap_trampoline, \"a\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_data",
+    ".global ap_trampoline_end",
+    // the assembler only accepts one symbol in a memory operand
+    ".set .Lap_gdt_offset, .Lap_gdt - ap_trampoline_start",
+    ".set .Lap_gdt_pointer_offset, .Lap_gdt_pointer - ap_trampoline_start",
+    ".set .Lap_protected_mode_offset, .Lap_protected_mode - ap_trampoline_start",
+    ".set .Lap_protected_mode_pointer_offset, .Lap_protected_mode_pointer - ap_trampoline_start",
+    ".set .Lap_long_mode_offset, .Lap_long_mode - ap_trampoline_start",
+    ".set .Lap_long_mode_pointer_offset, .Lap_long_mode_pointer - ap_trampoline_start",
+    ".set .Lap_data_offset, ap_trampoline_data - ap_trampoline_start",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    // the physical address of the trampoline, which completes the pointers below
+    "movzx ebx, ax",
+    "shl ebx, 4",
+    "lea eax, [ebx + .Lap_gdt_offset]",
+    "mov dword ptr [.Lap_gdt_pointer_offset + 2], eax",
+    "lea eax, [ebx + .Lap_protected_mode_offset]",
+    "mov dword ptr [.Lap_protected_mode_pointer_offset], eax",
+    "lea eax, [ebx + .Lap_long_mode_offset]",
+    "mov dword ptr [.Lap_long_mode_pointer_offset], eax",
+    "lgdt [.Lap_gdt_pointer_offset]",
+    "mov eax, cr0",
+    "or eax, 1",
+    "mov cr0, eax",
+    "jmp fword ptr [.Lap_protected_mode_pointer_offset]",
+    ".code32",
+    ".Lap_protected_mode:",
+    "mov ax, {data_selector}",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov eax, [ebx + .Lap_data_offset + {cr4}]",
+    "mov cr4, eax",
+    "mov eax, [ebx + .Lap_data_offset + {cr3}]",
+    "mov cr3, eax",
+    "mov ecx, 0xc0000080",
+    "mov eax, [ebx + .Lap_data_offset + {efer}]",
+    "mov edx, [ebx + .Lap_data_offset + {efer} + 4]",
+    "wrmsr",
+    // enabling paging with long mode enabled in EFER activates long mode
+    "mov eax, [ebx + .Lap_data_offset + {cr0}]",
+    "mov cr0, eax",
+    "jmp fword ptr [ebx + .Lap_long_mode_pointer_offset]",
+    ".code64",
+    ".Lap_long_mode:",
+    "mov ax, {data_selector}",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    // the upper half of the registers is undefined after leaving protected mode
+    "mov ebx, ebx",
+    "mov rsp, [rbx + .Lap_data_offset + {stack_top}]",
+    "mov rdi, [rbx + .Lap_data_offset + {argument}]",
+    "mov rax, [rbx + .Lap_data_offset + {entry}]",
+    "call rax",
+    "ud2",
+    ".align 8",
+    ".Lap_gdt:",
+    ".quad 0",
+    ".quad 0x00cf9a000000ffff",
+    ".quad 0x00cf92000000ffff",
+    ".quad 0x00af9a000000ffff",
+    ".Lap_gdt_pointer:",
+    ".word 4 * 8 - 1",
+    ".long 0",
+    ".Lap_protected_mode_pointer:",
+    ".long 0",
+    ".word {code_32_selector}",
+    ".Lap_long_mode_pointer:",
+    ".long 0",
+    ".word {code_64_selector}",
+    ".align 8",
+    "ap_trampoline_data:",
+    ".fill {data_size}, 1, 0",
+    "ap_trampoline_end:",
+    ".popsection",
+    data_selector = const DATA_SELECTOR,
+    code_32_selector = const CODE_32_SELECTOR,
+    code_64_selector = const CODE_64_SELECTOR,
+    cr0 = const mem::offset_of!(TrampolineData, cr0),
+    cr3 = const mem::offset_of!(TrampolineData, cr3),
+    cr4 = const mem::offset_of!(TrampolineData, cr4),
+    efer = const mem::offset_of!(TrampolineData, efer),
+    stack_top = const mem::offset_of!(TrampolineData, stack_top),
+    entry = const mem::offset_of!(TrampolineData, entry),
+    argument = const mem::offset_of!(TrampolineData, argument),
+    data_size = const mem::size_of::<TrampolineData>(),
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_data: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// The code APs start with, copied to a page in low memory that is identity mapped in a private
+/// copy of the kernel page table while the trampoline exists.
+pub(super) struct Trampoline {
+    frame: PhysFrame,
+    /// A copy of the level 4 page table of the kernel below 4 GiB.
+    page_table: PhysFrame,
+    /// The copies of the level 3, 2 and 1 tables on the way to the identity mapping.
+    tables: [PhysFrame; 3],
+}
+
+impl Trampoline {
+    pub(super) fn new() -> Result<Self, SmpError> {
+        let code = unsafe {
+            let start = ptr::addr_of!(ap_trampoline_start);
+            let end = ptr::addr_of!(ap_trampoline_end);
+            slice::from_raw_parts(start, end.offset_from(start) as usize)
+        };
+
+        let mut frame_allocator = frame_allocator();
+        let (frame, page_table) = {
+            let frame = frame_allocator.allocate_below(1, TRAMPOLINE_LIMIT);
+            let page_table = frame_allocator.allocate_below(1, PAGE_TABLE_LIMIT);
+
+            match (frame, page_table) {
+                (Some(frame), Some(page_table)) => (frame.start, page_table.start),
+                (frame, page_table) => {
+                    for range in frame.into_iter().chain(page_table) {
+                        unsafe { frame_allocator.deallocate_frame(range.start) };
+                    }
+                    return Err(SmpError::NoLowMemory);
+                }
+            }
+        };
+        let trampoline = match [(); 3].map(|_| frame_allocator.allocate_frame()) {
+            [Some(level_3), Some(level_2), Some(level_1)] => Self {
+                frame,
+                page_table,
+                tables: [level_3, level_2, level_1],
+            },
+            tables => {
+                for table in [frame, page_table]
+                    .into_iter()
+                    .chain(tables.into_iter().flatten())
+                {
+                    unsafe { frame_allocator.deallocate_frame(table) };
+                }
+                return Err(SmpError::Map(MapToError::FrameAllocationFailed));
+            }
+        };
+        drop(frame_allocator);
+
+        unsafe {
+            ptr::copy_nonoverlapping(
+                frame_virtual_address(Cr3::read().0).as_ptr::<PageTable>(),
+                frame_virtual_address(page_table).as_mut_ptr::<PageTable>(),
+                1,
+            );
+            ptr::copy_nonoverlapping(
+                code.as_ptr(),
+                frame_virtual_address(frame).as_mut_ptr::<u8>(),
+                code.len(),
+            );
+        }
+
+        // the instructions after enabling paging are fetched from the physical address. Only the
+        // copies of the tables on the way to it are changed, so the kernel page table stays as it
+        // is and the other mappings are shared.
+        let page =
+            Page::<Size4KiB>::containing_address(VirtAddr::new(frame.start_address().as_u64()));
+        let mut table = table_mut(page_table);
+        for (index, &copy) in [page.p4_index(), page.p3_index(), page.p2_index()]
+            .into_iter()
+            .zip(&trampoline.tables)
+        {
+            table = copy_table(&mut table[index], copy)?;
+        }
+        table[page.p1_index()].set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+
+        Ok(trampoline)
+    }
+
+    /// The startup IPI vector that starts an AP at the trampoline.
+    pub(super) fn vector(&self) -> u8 {
+        (self.frame.start_address().as_u64() / 4096) as u8
+    }
+
+    /// Makes the next AP that runs the trampoline call `entry(argument)` on the stack that ends
+    /// at `stack_top`, with the control registers of the current CPU.
+    pub(super) fn prepare(
+        &mut self,
+        entry: extern "C" fn(usize) -> !,
+        argument: usize,
+        stack_top: VirtAddr,
+    ) {
+        let data = TrampolineData {
+            cr0: Cr0::read_raw(),
+            cr3: self.page_table.start_address().as_u64(),
+            // PCIDs cannot be enabled outside of long mode
+            cr4: Cr4::read_raw() & !Cr4Flags::PCID.bits(),
+            efer: Efer::read_raw() & !EferFlags::LONG_MODE_ACTIVE.bits(),
+            stack_top: stack_top.as_u64(),
+            entry: entry as *const () as u64,
+            argument: argument as u64,
+        };
+
+        let address = frame_virtual_address(self.frame) + self.data_offset() as u64;
+        unsafe { address.as_mut_ptr::<TrampolineData>().write_volatile(data) };
+    }
+
+    fn data_offset(&self) -> usize {
+        unsafe {
+            ptr::addr_of!(ap_trampoline_data).offset_from(ptr::addr_of!(ap_trampoline_start))
+                as usize
+        }
+    }
+}
+
+impl Drop for Trampoline {
+    fn drop(&mut self) {
+        let mut frame_allocator = frame_allocator();
+        for frame in [self.frame, self.page_table].into_iter().chain(self.tables) {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Points `entry` to `copy`, which is filled with the table the entry pointed to before or
+/// cleared if there was none.
+fn copy_table(
+    entry: &mut PageTableEntry,
+    copy: PhysFrame,
+) -> Result<&'static mut PageTable, SmpError> {
+    let table = table_mut(copy);
+    let flags = match entry.frame() {
+        Ok(shared) => {
+            *table = table_mut(shared).clone();
+            entry.flags()
+        }
+        Err(FrameError::FrameNotPresent) => {
+            table.zero();
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE
+        }
+        Err(FrameError::HugeFrame) => return Err(SmpError::Map(MapToError::ParentEntryHugePage)),
+    };
+
+    entry.set_frame(copy, flags);
+    Ok(table)
+}
+
+fn table_mut(frame: PhysFrame) -> &'static mut PageTable {
+    unsafe { &mut *frame_virtual_address(frame).as_mut_ptr::<PageTable>() }
+}
+
+fn frame_virtual_address(frame: PhysFrame) -> VirtAddr {
+    physical_to_virtual(frame.start_address())
+}
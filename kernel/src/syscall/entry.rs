@@ -0,0 +1,104 @@
+use core::arch::naked_asm;
+use klib::percpu::{KERNEL_STACK_OFFSET, USER_STACK_OFFSET};
+
+use super::dispatch;
+
+/// The registers of a system call, in the order the entry points push them. The number in rax
+/// is replaced by the return value.
+#[repr(C)]
+pub(super) struct SyscallFrame {
+    pub(super) rax: u64,
+    pub(super) rdi: u64,
+    pub(super) rsi: u64,
+    pub(super) rdx: u64,
+    pub(super) r10: u64,
+    pub(super) r8: u64,
+    pub(super) r9: u64,
+}
+
+/// Where `syscall` enters the kernel, with the return address in rcx, the flags of user mode in
+/// r11 and interrupts disabled by SFMASK. It switches to the kernel entry stack of the thread in
+/// the per-CPU data, and returns with `sysretq` on the stack of user mode.
+///
+/// All registers but rax, rcx and r11 keep their values.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn syscall_entry() {
+    naked_asm!(
+        "swapgs",
+        "mov qword ptr gs:[{user_stack}], rsp",
+        "mov rsp, qword ptr gs:[{kernel_stack}]",
+        "push qword ptr gs:[{user_stack}]",
+        "push r11",
+        "push rcx",
+        "push r9",
+        "push r8",
+        "push r10",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rax",
+        "mov rdi, rsp",
+        "mov rsi, r11",
+        "call {dispatch}",
+        "pop rax",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop r10",
+        "pop r8",
+        "pop r9",
+        "pop rcx",
+        "pop r11",
+        // `dispatch` returns with interrupts disabled, so nothing runs on the stack of user mode
+        "pop rsp",
+        "swapgs",
+        "sysretq",
+        user_stack = const USER_STACK_OFFSET,
+        kernel_stack = const KERNEL_STACK_OFFSET,
+        dispatch = sym dispatch,
+    )
+}
+
+/// The handler of `int 0x80`, which takes system calls like [`syscall_entry`] but keeps rcx and
+/// r11. The kernel can use it as well.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn interrupt_entry() {
+    naked_asm!(
+        // interrupts do not clear the direction flag
+        "cld",
+        // the lowest bits of the saved code segment are the privilege level of the caller
+        "test qword ptr [rsp + 8], 3",
+        "jz 2f",
+        "swapgs",
+        "2:",
+        "push rcx",
+        "push r11",
+        "push r9",
+        "push r8",
+        "push r10",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push rax",
+        "mov rdi, rsp",
+        // the flags of the caller, above the 9 registers and the return address and code segment
+        // of the interrupt stack frame
+        "mov rsi, qword ptr [rsp + 88]",
+        "call {dispatch}",
+        "pop rax",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop r10",
+        "pop r8",
+        "pop r9",
+        "pop r11",
+        "pop rcx",
+        "test qword ptr [rsp + 8], 3",
+        "jz 3f",
+        "swapgs",
+        "3:",
+        "iretq",
+        dispatch = sym dispatch,
+    )
+}
@@ -0,0 +1,260 @@
+mod entry;
+
+use alloc::{string::String, vec};
+use core::{fmt, time::Duration};
+use klib::io::print;
+use x86_64::{
+    instructions::interrupts,
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask, Star},
+        rflags::RFlags,
+    },
+    structures::idt::InterruptDescriptorTable,
+    PrivilegeLevel, VirtAddr,
+};
+
+use crate::{
+    gdt, process, thread,
+    time::{self, SystemTime},
+    user::{self, ExitStatus},
+};
+use entry::SyscallFrame;
+
+/// Vector of `int 0x80`, which takes system calls like the `syscall` instruction.
+pub const SYSCALL_VECTOR: u8 = 0x80;
+
+// System call numbers, passed in rax. The arguments are passed in rdi, rsi, rdx, r10, r8 and r9,
+// and the result is returned in rax.
+
+/// Prints the UTF-8 text at rdi with the length rsi to the terminal. Returns the number of bytes
+/// written, which may be less than the length.
+pub const WRITE: u64 = 0;
+/// Ends the program with the exit code in edi.
+pub const EXIT: u64 = 1;
+/// Lets other threads run.
+pub const YIELD: u64 = 2;
+/// Sleeps for at least rdi nanoseconds.
+pub const SLEEP: u64 = 3;
+/// Returns the nanoseconds of the clock rdi, one of [`CLOCK_MONOTONIC`] and [`CLOCK_REALTIME`].
+pub const TIME: u64 = 4;
+
+/// Counts from an arbitrary point at boot and never jumps.
+pub const CLOCK_MONOTONIC: u64 = 0;
+/// Counts from the Unix epoch.
+pub const CLOCK_REALTIME: u64 = 1;
+
+/// Maximum number of bytes one [`WRITE`] prints.
+const MAX_WRITE_LENGTH: usize = 4096;
+
+type Arguments = [u64; 6];
+type SyscallHandler = fn(&Arguments) -> Result<u64, SyscallError>;
+
+/// Failed system calls return the negated error number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum SyscallError {
+    NoSuchSyscall = 1,
+    /// A buffer is not mapped accessible to user mode.
+    BadAddress = 2,
+    InvalidArgument = 3,
+}
+
+impl SyscallError {
+    pub fn into_return_value(self) -> u64 {
+        (-(self as i64)) as u64
+    }
+}
+
+impl fmt::Display for SyscallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoSuchSyscall => write!(f, "no such system call"),
+            Self::BadAddress => write!(f, "bad address"),
+            Self::InvalidArgument => write!(f, "invalid argument"),
+        }
+    }
+}
+
+/// Lets user mode enter the kernel with `syscall` on the current CPU. The GDT must be loaded.
+pub(crate) fn init() {
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.data_selector,
+    )
+    .expect("the GDT segments are not in the order syscall expects");
+    LStar::write(VirtAddr::new(entry::syscall_entry as *const () as u64));
+    // interrupts stay disabled until the entry point switched to the kernel stack
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// Installs the handler of `int 0x80`, which user mode may call.
+pub(crate) fn set_interrupt_handler(idt: &mut InterruptDescriptorTable) {
+    let handler = VirtAddr::new(entry::interrupt_entry as *const () as u64);
+    unsafe {
+        idt[SYSCALL_VECTOR as usize]
+            .set_handler_addr(handler)
+            .set_privilege_level(PrivilegeLevel::Ring3)
+    };
+}
+
+/// Returns the handler of the system call `number`.
+fn handler(number: u64) -> Option<SyscallHandler> {
+    match number {
+        WRITE => Some(sys_write),
+        EXIT => Some(sys_exit),
+        YIELD => Some(sys_yield),
+        SLEEP => Some(sys_sleep),
+        TIME => Some(sys_time),
+        _ => None,
+    }
+}
+
+/// Runs the system call in `frame` and stores its result in rax. Interrupts are enabled while it
+/// runs if they are in `caller_flags`, which user mode always has. Returns with interrupts
+/// disabled, as the entry points need it.
+extern "C" fn dispatch(frame: &mut SyscallFrame, caller_flags: u64) {
+    // the kernel may use `int 0x80` with interrupts disabled
+    if RFlags::from_bits_truncate(caller_flags).contains(RFlags::INTERRUPT_FLAG) {
+        interrupts::enable();
+    }
+
+    let arguments = [
+        frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8, frame.r9,
+    ];
+    let result = handler(frame.rax)
+        .ok_or(SyscallError::NoSuchSyscall)
+        .and_then(|handler| handler(&arguments));
+
+    frame.rax = match result {
+        Ok(value) => value,
+        Err(error) => error.into_return_value(),
+    };
+
+    interrupts::disable();
+}
+
+fn sys_write(&[buffer, length, ..]: &Arguments) -> Result<u64, SyscallError> {
+    let buffer = VirtAddr::try_new(buffer).map_err(|_| SyscallError::BadAddress)?;
+    let mut bytes = vec![0; (length as usize).min(MAX_WRITE_LENGTH)];
+    // the kernel has no user memory to print from
+    let process = process::current().ok_or(SyscallError::BadAddress)?;
+    process
+        .address_space()
+        .read(buffer, &mut bytes)
+        .map_err(|_| SyscallError::BadAddress)?;
+
+    print!("{}", String::from_utf8_lossy(&bytes));
+    Ok(bytes.len() as u64)
+}
+
+fn sys_exit(&[code, ..]: &Arguments) -> Result<u64, SyscallError> {
+    // the kernel can call `int 0x80` as well, but it has no program to end
+    if thread::kernel_entry_stack().is_none() {
+        return Err(SyscallError::InvalidArgument);
+    }
+
+    unsafe { user::exit(ExitStatus::Exited(code as i32)) }
+}
+
+fn sys_yield(_: &Arguments) -> Result<u64, SyscallError> {
+    thread::yield_now();
+    Ok(0)
+}
+
+fn sys_sleep(&[nanoseconds, ..]: &Arguments) -> Result<u64, SyscallError> {
+    time::sleep(Duration::from_nanos(nanoseconds));
+    Ok(0)
+}
+
+fn sys_time(&[clock, ..]: &Arguments) -> Result<u64, SyscallError> {
+    match clock {
+        CLOCK_MONOTONIC => Ok(time::nanoseconds()),
+        CLOCK_REALTIME => {
+            let since_epoch = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            Ok(since_epoch.as_nanos() as u64)
+        }
+        _ => Err(SyscallError::InvalidArgument),
+    }
+}
+
+#[test_case]
+fn programs_make_system_calls() {
+    use crate::{
+        memory::{AddressSpace, USER_START},
+        process::Process,
+    };
+    use alloc::vec::Vec;
+    use x86_64::structures::paging::{PageSize, PageTableFlags, Size4KiB};
+
+    /// Encodes `mov eax, number; mov rdi, first; mov rsi, second; syscall`.
+    fn system_call(number: u64, first: u64, second: u64) -> impl Iterator<Item = u8> {
+        [0xb8]
+            .into_iter()
+            .chain((number as u32).to_le_bytes())
+            .chain([0x48, 0xbf])
+            .chain(first.to_le_bytes())
+            .chain([0x48, 0xbe])
+            .chain(second.to_le_bytes())
+            .chain([0x0f, 0x05])
+    }
+
+    let code = USER_START;
+    let message = b"Hello from user mode\n";
+    let message_address = code + 0x800u64;
+
+    let program: Vec<u8> = system_call(WRITE, message_address.as_u64(), message.len() as u64)
+        .chain(system_call(YIELD, 0, 0))
+        .chain(system_call(SLEEP, 1_000_000, 0))
+        .chain(system_call(EXIT, 42, 0))
+        .collect();
+
+    let mut address_space = AddressSpace::new().unwrap();
+    address_space
+        .map(code, Size4KiB::SIZE, PageTableFlags::WRITABLE)
+        .unwrap();
+    address_space.write(code, &program).unwrap();
+    address_space.write(message_address, message).unwrap();
+
+    let stack_pointer = code + Size4KiB::SIZE;
+    let handle = Process::new("user test", address_space)
+        .spawn_thread(code, stack_pointer)
+        .unwrap();
+    assert_eq!(handle.join(), ExitStatus::Exited(42));
+}
+
+#[test_case]
+fn the_kernel_can_call_int_0x80() {
+    use core::arch::asm;
+
+    let call = |number: u64, argument: u64| {
+        let result: u64;
+        unsafe { asm!("int 0x80", inlateout("rax") number => result, in("rdi") argument) };
+        result
+    };
+
+    let before = time::nanoseconds();
+    let now = call(TIME, CLOCK_MONOTONIC);
+    assert!(before <= now && now <= time::nanoseconds());
+
+    assert_eq!(
+        call(TIME, 2),
+        SyscallError::InvalidArgument.into_return_value()
+    );
+    assert_eq!(
+        call(EXIT, 0),
+        SyscallError::InvalidArgument.into_return_value()
+    );
+    assert_eq!(call(99, 0), SyscallError::NoSuchSyscall.into_return_value());
+}
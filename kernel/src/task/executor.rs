@@ -0,0 +1,141 @@
+use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use core::{
+    future::Future,
+    task::{Context, Waker},
+};
+use crossbeam_queue::ArrayQueue;
+use klib::sync::WaitQueue;
+
+use super::{Task, TaskId};
+
+/// Maximum number of tasks that can be woken at the same time.
+const READY_QUEUE_SIZE: usize = 128;
+
+/// A cooperative executor that polls its tasks when they are woken, and puts its thread to sleep
+/// when none of them is ready.
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    /// Tasks that were woken since they were last polled. Wakers push to it from interrupt
+    /// handlers, so it must not lock.
+    ready_queue: Arc<ArrayQueue<TaskId>>,
+    /// Where the thread of the executor waits for a task to be woken.
+    idle: Arc<WaitQueue>,
+    wakers: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self {
+            tasks: BTreeMap::new(),
+            ready_queue: Arc::new(ArrayQueue::new(READY_QUEUE_SIZE)),
+            idle: Arc::new(WaitQueue::new()),
+            wakers: BTreeMap::new(),
+        }
+    }
+
+    pub fn spawn(&mut self, future: impl Future<Output = ()> + 'static) {
+        let task = Task::new(future);
+        let id = task.id;
+
+        self.tasks.insert(id, task);
+        self.ready_queue.push(id).expect("ready queue is full");
+    }
+
+    /// Runs the tasks forever.
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        while let Some(id) = self.ready_queue.pop() {
+            // a task can be woken after it completed
+            let Some(task) = self.tasks.get_mut(&id) else {
+                continue;
+            };
+
+            let waker = self.wakers.entry(id).or_insert_with(|| {
+                TaskWaker::waker(id, self.ready_queue.clone(), self.idle.clone())
+            });
+            let mut context = Context::from_waker(waker);
+
+            if task.poll(&mut context).is_ready() {
+                self.tasks.remove(&id);
+                self.wakers.remove(&id);
+            }
+        }
+    }
+
+    /// Blocks the thread until a task is woken, so that threads of lower priority can run.
+    fn sleep_if_idle(&self) {
+        self.idle.wait_until(|| !self.ready_queue.is_empty());
+    }
+}
+
+struct TaskWaker {
+    id: TaskId,
+    ready_queue: Arc<ArrayQueue<TaskId>>,
+    idle: Arc<WaitQueue>,
+}
+
+impl TaskWaker {
+    fn waker(id: TaskId, ready_queue: Arc<ArrayQueue<TaskId>>, idle: Arc<WaitQueue>) -> Waker {
+        Waker::from(Arc::new(Self {
+            id,
+            ready_queue,
+            idle,
+        }))
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.ready_queue.push(self.id).expect("ready queue is full");
+        self.idle.notify_one();
+    }
+}
+
+#[test_case]
+fn woken_tasks_are_polled_again() {
+    use alloc::rc::Rc;
+    use core::{cell::Cell, pin::Pin, task::Poll};
+
+    /// Returns pending once after waking itself.
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            context.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    let steps = Rc::new(Cell::new(0));
+    let mut executor = Executor::new();
+
+    for _ in 0..2 {
+        let steps = steps.clone();
+        executor.spawn(async move {
+            steps.set(steps.get() + 1);
+            YieldNow(false).await;
+            steps.set(steps.get() + 1);
+        });
+    }
+
+    executor.run_ready_tasks();
+    assert_eq!(steps.get(), 4);
+    assert!(executor.tasks.is_empty());
+    assert!(executor.wakers.is_empty());
+}
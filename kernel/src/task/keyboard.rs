@@ -0,0 +1,120 @@
+use conquer_once::spin::OnceCell;
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use crossbeam_queue::ArrayQueue;
+use futures_util::{
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+use klib::io::{print, println};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
+use x86_64::instructions::port::Port;
+
+use crate::{
+    interrupts::{register_irq_handler, IrqMode, IrqReturn, KEYBOARD_IRQ},
+    thread,
+};
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+const STATUS_OUTPUT_FULL: u8 = 1;
+const SCANCODE_QUEUE_SIZE: usize = 128;
+
+static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Starts queueing the scancodes of the PS/2 keyboard.
+pub(crate) fn init() {
+    SCANCODE_QUEUE.init_once(|| ArrayQueue::new(SCANCODE_QUEUE_SIZE));
+
+    register_irq_handler(
+        KEYBOARD_IRQ,
+        "keyboard",
+        IrqMode::Exclusive,
+        keyboard_interrupt_handler,
+    )
+    .expect("failed to register the keyboard interrupt handler");
+}
+
+/// Only queues the scancode, so that the interrupt handler never waits for a lock.
+fn keyboard_interrupt_handler() -> IrqReturn {
+    let status: u8 = unsafe { Port::new(STATUS_PORT).read() };
+    if status & STATUS_OUTPUT_FULL == 0 {
+        return IrqReturn::NotHandled;
+    }
+
+    let scancode: u8 = unsafe { Port::new(DATA_PORT).read() };
+
+    match SCANCODE_QUEUE.get().unwrap().push(scancode) {
+        Ok(()) => WAKER.wake(),
+        Err(_) => log::warn!("Scancode queue is full, dropping keyboard input"),
+    }
+
+    IrqReturn::Handled
+}
+
+/// The scancodes received from the keyboard. There must be only one reader.
+struct ScancodeStream {
+    queue: &'static ArrayQueue<u8>,
+}
+
+impl ScancodeStream {
+    fn new() -> Self {
+        Self {
+            queue: SCANCODE_QUEUE
+                .get()
+                .expect("the keyboard is not initialized"),
+        }
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<u8>> {
+        if let Some(scancode) = self.queue.pop() {
+            return Poll::Ready(Some(scancode));
+        }
+
+        // a scancode queued after the first check but before registering would not wake us,
+        // so the queue is checked again
+        WAKER.register(context.waker());
+        match self.queue.pop() {
+            Some(scancode) => {
+                WAKER.take();
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Decodes the keyboard input and prints the typed characters. F12 prints the threads and the
+/// CPU time they used.
+pub async fn print_keypresses() {
+    let mut scancodes = ScancodeStream::new();
+    let mut keyboard = Keyboard::new(
+        ScancodeSet1::new(),
+        layouts::Us104Key,
+        HandleControl::Ignore,
+    );
+
+    while let Some(scancode) = scancodes.next().await {
+        if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+            match keyboard.process_keyevent(key_event) {
+                Some(DecodedKey::Unicode(character)) => print!("{}", character),
+                Some(DecodedKey::RawKey(KeyCode::F12)) => print_threads(),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn print_threads() {
+    print!("\n");
+    for thread in thread::threads() {
+        println!("{thread}");
+    }
+}
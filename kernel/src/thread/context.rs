@@ -0,0 +1,84 @@
+use core::arch::naked_asm;
+use x86_64::VirtAddr;
+
+/// The callee-saved registers `switch` pushes, in the order they are on the stack, followed by
+/// its return address.
+#[repr(C)]
+struct SwitchFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    rbx: u64,
+    rbp: u64,
+    return_address: u64,
+}
+
+/// Saves the callee-saved registers and the stack pointer of the current thread to `from`, and
+/// resumes the thread whose stack pointer is `to`.
+///
+/// Returns when another thread switches back to the saved stack pointer.
+///
+/// ## Safety
+///
+/// `to` must be a stack pointer saved by `switch` or returned by [`initial_stack_pointer`], and
+/// `from` must be valid for writes. Interrupts must be disabled.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn switch(from: *mut u64, to: u64) {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov [rdi], rsp",
+        "mov rsp, rsi",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+    )
+}
+
+/// The first code of every thread. `switch` returns here with the entry point in r13 and its
+/// argument in r12.
+#[unsafe(naked)]
+unsafe extern "C" fn thread_trampoline() -> ! {
+    naked_asm!("mov rdi, r12", "call r13", "ud2")
+}
+
+/// Prepares the stack below `stack_top` so that switching to the returned stack pointer calls
+/// `entry(argument)`.
+///
+/// ## Safety
+///
+/// The stack must be mapped, unused and 16 byte aligned at `stack_top`.
+#[deny(unsafe_op_in_unsafe_fn)]
+pub(super) unsafe fn initial_stack_pointer(
+    stack_top: VirtAddr,
+    entry: extern "C" fn(usize) -> !,
+    argument: usize,
+) -> u64 {
+    // the trampoline starts with a 16 byte aligned stack pointer, so that its call enters
+    // `entry` with the alignment the ABI requires
+    let frame = (stack_top - 16u64 - core::mem::size_of::<SwitchFrame>() as u64)
+        .as_mut_ptr::<SwitchFrame>();
+
+    unsafe {
+        frame.write(SwitchFrame {
+            r15: 0,
+            r14: 0,
+            r13: entry as *const () as u64,
+            r12: argument as u64,
+            rbx: 0,
+            rbp: 0,
+            return_address: thread_trampoline as *const () as u64,
+        })
+    };
+
+    frame as u64
+}
@@ -0,0 +1,623 @@
+mod context;
+mod parking;
+mod scheduler;
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use klib::{interrupts::UninterruptibleMutex, percpu};
+use x86_64::{instructions::interrupts, VirtAddr};
+
+use crate::{
+    memory::{KernelStack, VmmError},
+    process::Process,
+    time,
+};
+use scheduler::{reschedule, scheduler};
+
+pub(crate) use scheduler::{preempt, tick};
+
+/// Size of the stack of spawned threads.
+pub const STACK_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for ThreadId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Threads with a higher priority always run before ready threads with a lower one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Only runs when no other thread is ready.
+    Idle,
+    Normal,
+    High,
+}
+
+impl Priority {
+    const COUNT: usize = 3;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThreadState {
+    Running,
+    Ready,
+    /// Waiting until another thread wakes it.
+    Blocked,
+    /// Finished, but not joined yet.
+    Exited,
+}
+
+struct Thread {
+    name: &'static str,
+    priority: Priority,
+    state: ThreadState,
+    /// The stack pointer saved by the last switch away from the thread.
+    stack_pointer: u64,
+    /// `None` for the boot thread, and after the thread exited.
+    stack: Option<KernelStack>,
+    /// The thread waiting in [`JoinHandle::join`].
+    joiner: Option<ThreadId>,
+    /// Whether the [`JoinHandle`] was dropped, so the thread is forgotten when it exits.
+    detached: bool,
+    /// Nanoseconds the thread ran, up to its last switch.
+    cpu_time: u64,
+    /// Set when the thread was unparked while it was not parked, so that it does not park.
+    unpark_token: bool,
+    /// Whether a CPU runs the thread or did not finish switching away from it yet. Only threads
+    /// that are not on a CPU are queued, joined or freed.
+    on_cpu: bool,
+    /// Where the kernel stack starts for interrupts and system calls while the thread runs in
+    /// user mode, or `None` if it does not.
+    kernel_entry_stack: Option<VirtAddr>,
+    /// The process whose address space the thread runs in, or `None` for kernel threads, which
+    /// run with the page table of the kernel. Released when the thread exited.
+    process: Option<Arc<Process>>,
+}
+
+/// A snapshot of the accounting of a thread.
+#[derive(Debug, Clone)]
+pub struct ThreadInfo {
+    pub id: ThreadId,
+    pub name: &'static str,
+    pub priority: Priority,
+    pub cpu_time: Duration,
+}
+
+impl fmt::Display for ThreadInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>4} {:<16} {:?}, ran {:?}",
+            self.id, self.name, self.priority, self.cpu_time
+        )
+    }
+}
+
+/// Owns a spawned thread. Dropping it detaches the thread.
+pub struct JoinHandle<T> {
+    id: ThreadId,
+    result: Arc<UninterruptibleMutex<Option<T>>>,
+}
+
+/// Turns the code running since boot into the first thread, named "main", starts the idle
+/// thread and enables preemption. From then on, the blocking primitives of [`klib::sync`] put
+/// waiting threads to sleep.
+pub(crate) fn init() {
+    let main = Box::new(Thread {
+        name: "main",
+        priority: Priority::Normal,
+        state: ThreadState::Running,
+        stack_pointer: 0,
+        stack: None,
+        joiner: None,
+        detached: true,
+        cpu_time: 0,
+        unpark_token: false,
+        on_cpu: true,
+        kernel_entry_stack: None,
+        process: None,
+    });
+    let idle_thread = Thread::new("idle", Priority::Idle, None, Box::new(|| idle()))
+        .expect("failed to create the idle thread");
+
+    let main_id = ThreadId::new();
+    let idle_id = ThreadId::new();
+
+    let mut scheduler = scheduler();
+    scheduler.add(main_id, main);
+    // the idle thread is not queued, the scheduler picks it when no other thread is ready
+    scheduler.threads.insert(idle_id, idle_thread);
+    scheduler.set_idle(idle_id);
+    scheduler.set_current(main_id);
+    scheduler.account_cpu_time();
+    drop(scheduler);
+
+    klib::sync::set_scheduler(&parking::Parking).expect("threads are already initialized");
+}
+
+/// Turns the code an AP runs after it started into the idle thread of the CPU, which must run
+/// [`idle`] afterwards. The APs do not pick other threads yet.
+pub(crate) fn init_ap() {
+    let idle_thread = Box::new(Thread {
+        name: "idle",
+        priority: Priority::Idle,
+        state: ThreadState::Running,
+        stack_pointer: 0,
+        stack: None,
+        joiner: None,
+        detached: true,
+        cpu_time: 0,
+        unpark_token: false,
+        on_cpu: true,
+        kernel_entry_stack: None,
+        process: None,
+    });
+    let id = ThreadId::new();
+
+    interrupts::without_interrupts(|| {
+        let mut scheduler = scheduler();
+        scheduler.threads.insert(id, idle_thread);
+        scheduler.set_idle(id);
+        scheduler.set_current(id);
+        scheduler.account_cpu_time();
+    });
+}
+
+/// Halts the CPU until the next interrupt, forever. This is what the idle threads run.
+pub fn idle() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Starts running `f` on a new thread with normal priority. Its return value can be retrieved
+/// with [`JoinHandle::join`].
+pub fn spawn<F, T>(name: &'static str, f: F) -> Result<JoinHandle<T>, VmmError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_with_priority(name, Priority::Normal, f)
+}
+
+pub fn spawn_with_priority<F, T>(
+    name: &'static str,
+    priority: Priority,
+    f: F,
+) -> Result<JoinHandle<T>, VmmError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_thread(name, priority, None, f)
+}
+
+/// Starts running `f` on a new thread with normal priority in the address space of `process`.
+pub(crate) fn spawn_in_process<F, T>(
+    name: &'static str,
+    process: Arc<Process>,
+    f: F,
+) -> Result<JoinHandle<T>, VmmError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_thread(name, Priority::Normal, Some(process), f)
+}
+
+fn spawn_thread<F, T>(
+    name: &'static str,
+    priority: Priority,
+    process: Option<Arc<Process>>,
+    f: F,
+) -> Result<JoinHandle<T>, VmmError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let result = Arc::new(UninterruptibleMutex::new(None));
+    let thread_result = result.clone();
+    let thread = Thread::new(
+        name,
+        priority,
+        process,
+        Box::new(move || {
+            let value = f();
+            *thread_result.lock() = Some(value);
+        }),
+    )?;
+
+    let id = ThreadId::new();
+    scheduler().add(id, thread);
+
+    log::debug!("Spawned thread {id} ({name})");
+    Ok(JoinHandle { id, result })
+}
+
+impl Thread {
+    /// Creates a ready thread that runs `entry` on a new stack.
+    fn new(
+        name: &'static str,
+        priority: Priority,
+        process: Option<Arc<Process>>,
+        entry: Box<dyn FnOnce()>,
+    ) -> Result<Box<Self>, VmmError> {
+        let stack = KernelStack::new(name, STACK_SIZE)?;
+
+        // a thin pointer to the closure is passed in a register
+        let argument = Box::into_raw(Box::new(entry)) as usize;
+        let stack_pointer =
+            unsafe { context::initial_stack_pointer(stack.top(), thread_start, argument) };
+
+        Ok(Box::new(Self {
+            name,
+            priority,
+            state: ThreadState::Ready,
+            stack_pointer,
+            stack: Some(stack),
+            joiner: None,
+            detached: false,
+            cpu_time: 0,
+            unpark_token: false,
+            on_cpu: false,
+            kernel_entry_stack: None,
+            process,
+        }))
+    }
+}
+
+extern "C" fn thread_start(entry: usize) -> ! {
+    scheduler::finish_switch();
+    interrupts::enable();
+
+    let entry = unsafe { Box::from_raw(entry as *mut Box<dyn FnOnce()>) };
+    entry();
+
+    exit();
+}
+
+/// Ends the current thread.
+pub fn exit() -> ! {
+    interrupts::disable();
+
+    let mut scheduler = scheduler();
+    log::trace!("Thread {} exited", scheduler.current_thread().name);
+
+    // the joiner is woken after the switch away from the thread
+    reschedule(scheduler, ThreadState::Exited);
+    unreachable!("exited thread was scheduled");
+}
+
+/// Returns the ID of the running thread. It does not lock the scheduler, so exception handlers
+/// can use it.
+pub fn current() -> ThreadId {
+    percpu::current()
+        .current_thread()
+        .map(ThreadId)
+        .expect("threads are not initialized")
+}
+
+/// The stack the current thread enters the kernel on from user mode, set by
+/// [`set_kernel_entry_stack`].
+pub(crate) fn kernel_entry_stack() -> Option<VirtAddr> {
+    scheduler().current_thread().kernel_entry_stack
+}
+
+/// Makes interrupts and system calls that arrive while the current thread runs in user mode start
+/// at `stack`, or records that the thread left user mode with `None`.
+pub(crate) fn set_kernel_entry_stack(stack: Option<VirtAddr>) {
+    let mut scheduler = scheduler();
+    scheduler.current_thread().kernel_entry_stack = stack;
+
+    if let Some(stack) = stack {
+        percpu::current().set_kernel_stack(stack);
+    }
+}
+
+/// The process the current thread belongs to, see [`crate::process::current`].
+pub(crate) fn current_process() -> Option<Arc<Process>> {
+    scheduler().current_thread().process.clone()
+}
+
+/// Blocks the current thread until the [`time::nanoseconds`] timestamp `deadline`. The timer
+/// wakes it at the first tick after the deadline, so lower priorities can run in the meantime.
+pub(crate) fn sleep_until(deadline: u64) {
+    interrupts::without_interrupts(|| {
+        // other wakeups, like a stale unpark, end the wait early
+        let current = current();
+        while time::nanoseconds() < deadline {
+            let mut guard = scheduler();
+            guard.wake_at(deadline, current);
+            reschedule(guard, ThreadState::Blocked);
+
+            scheduler().cancel_wake_at(deadline, current);
+        }
+    });
+}
+
+/// Lets other ready threads run before the current one continues.
+pub fn yield_now() {
+    interrupts::without_interrupts(|| reschedule(scheduler(), ThreadState::Ready));
+}
+
+/// Returns the accounting of all threads, including the time the current thread ran so far.
+pub fn threads() -> Vec<ThreadInfo> {
+    let mut scheduler = scheduler();
+    scheduler.account_cpu_time();
+
+    scheduler
+        .threads
+        .iter()
+        .map(|(&id, thread)| ThreadInfo {
+            id,
+            name: thread.name,
+            priority: thread.priority,
+            cpu_time: Duration::from_nanos(thread.cpu_time),
+        })
+        .collect()
+}
+
+impl<T> JoinHandle<T> {
+    /// Waits for the thread to finish and returns the value it returned.
+    pub fn join(self) -> T {
+        interrupts::without_interrupts(|| loop {
+            let mut scheduler = scheduler();
+            let current = scheduler.current().expect("threads are not initialized");
+            let thread = scheduler.threads.get_mut(&self.id).unwrap();
+
+            if thread.state == ThreadState::Exited && !thread.on_cpu {
+                scheduler.threads.remove(&self.id);
+                break;
+            }
+
+            thread.joiner = Some(current);
+            reschedule(scheduler, ThreadState::Blocked);
+        });
+
+        self.result
+            .lock()
+            .take()
+            .expect("joined thread did not return")
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        let mut scheduler = scheduler();
+
+        match scheduler.threads.get_mut(&self.id) {
+            Some(thread) if thread.state == ThreadState::Exited && !thread.on_cpu => {
+                scheduler.threads.remove(&self.id);
+            }
+            Some(thread) => thread.detached = true,
+            // the thread was joined
+            None => {}
+        }
+    }
+}
+
+#[test_case]
+fn threads_are_joined() {
+    let handles: [_; 3] = core::array::from_fn(|index| {
+        spawn("test", move || {
+            yield_now();
+            index * 2
+        })
+        .unwrap()
+    });
+
+    for (index, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join(), index * 2);
+    }
+}
+
+#[test_case]
+fn threads_are_preempted() {
+    use core::sync::atomic::AtomicBool;
+
+    static RAN: AtomicBool = AtomicBool::new(false);
+
+    let handle = spawn("test", || RAN.store(true, Ordering::Relaxed)).unwrap();
+
+    // the spawned thread can only run if the timer preempts this one
+    while !RAN.load(Ordering::Relaxed) {
+        core::hint::spin_loop();
+    }
+    handle.join();
+}
+
+#[test_case]
+fn higher_priorities_run_first() {
+    use core::sync::atomic::AtomicUsize;
+
+    static ORDER: AtomicUsize = AtomicUsize::new(0);
+
+    let position = || ORDER.fetch_add(1, Ordering::Relaxed);
+    // the timer must not let the first thread run before the second one is ready
+    let (normal, high) = interrupts::without_interrupts(|| {
+        (
+            spawn_with_priority("test", Priority::Normal, position).unwrap(),
+            spawn_with_priority("test", Priority::High, position).unwrap(),
+        )
+    });
+
+    assert_eq!(high.join(), 0);
+    assert_eq!(normal.join(), 1);
+}
+
+#[test_case]
+fn cpu_time_is_accounted() {
+    use crate::time::Instant;
+
+    let busy = spawn("test", || {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(20) {
+            core::hint::spin_loop();
+        }
+
+        let id = current();
+        threads()
+            .into_iter()
+            .find(|thread| thread.id == id)
+            .unwrap()
+            .cpu_time
+    })
+    .unwrap();
+
+    let cpu_time = busy.join();
+    assert!(cpu_time >= Duration::from_millis(15));
+    assert!(cpu_time < Duration::from_millis(200));
+}
+
+#[test_case]
+fn semaphores_block_threads() {
+    use klib::sync::Semaphore;
+
+    static SEMAPHORE: Semaphore = Semaphore::new(0);
+
+    let handles: [_; 2] = core::array::from_fn(|_| spawn("test", || SEMAPHORE.acquire()).unwrap());
+    yield_now();
+
+    for _ in &handles {
+        SEMAPHORE.release();
+    }
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(SEMAPHORE.available_permits(), 0);
+}
+
+#[test_case]
+fn condvars_wake_waiting_threads() {
+    use klib::sync::{Condvar, Mutex};
+
+    static READY: Mutex<bool> = Mutex::new(false);
+    static CONDVAR: Condvar = Condvar::new();
+
+    let waiter = spawn("test", || {
+        let ready = CONDVAR.wait_while(READY.lock(), |ready| !*ready);
+        assert!(*ready);
+    })
+    .unwrap();
+    yield_now();
+
+    *READY.lock() = true;
+    CONDVAR.notify_all();
+    waiter.join();
+}
+
+#[test_case]
+fn mutexes_block_and_exclude_threads() {
+    use klib::sync::Mutex;
+
+    static COUNTER: Mutex<usize> = Mutex::new(0);
+
+    let guard = COUNTER.lock();
+    let handles: [_; 3] = core::array::from_fn(|_| {
+        spawn("test", || {
+            for _ in 0..10 {
+                let mut counter = COUNTER.lock();
+                let value = *counter;
+                // another thread would see the old value if it got the lock now
+                yield_now();
+                *counter = value + 1;
+            }
+        })
+        .unwrap()
+    });
+    yield_now();
+
+    assert_eq!(*guard, 0);
+    drop(guard);
+    for handle in handles {
+        handle.join();
+    }
+    assert_eq!(*COUNTER.lock(), 30);
+}
+
+#[test_case]
+fn rwlocks_share_reads_and_exclude_writes() {
+    use klib::sync::RwLock;
+
+    static VALUE: RwLock<usize> = RwLock::new(1);
+
+    let read = VALUE.read();
+    let reader = spawn("test", || *VALUE.read()).unwrap();
+    assert_eq!(reader.join(), 1);
+
+    let writer = spawn("test", || *VALUE.write() = 2).unwrap();
+    yield_now();
+    assert!(VALUE.try_write().is_none());
+    assert_eq!(*read, 1);
+
+    drop(read);
+    writer.join();
+    let write = VALUE.write();
+    let reader = spawn("test", || *VALUE.read()).unwrap();
+    yield_now();
+    assert_eq!(*write, 2);
+
+    drop(write);
+    assert_eq!(reader.join(), 2);
+}
+
+#[test_case]
+fn wait_queues_wake_waiting_threads() {
+    use core::sync::atomic::AtomicBool;
+    use klib::sync::WaitQueue;
+
+    static QUEUE: WaitQueue = WaitQueue::new();
+    static OPEN: AtomicBool = AtomicBool::new(false);
+
+    assert!(!QUEUE.notify_one());
+    let handles: [_; 2] = core::array::from_fn(|_| {
+        spawn("test", || QUEUE.wait_until(|| OPEN.load(Ordering::Relaxed))).unwrap()
+    });
+    yield_now();
+
+    OPEN.store(true, Ordering::Relaxed);
+    QUEUE.notify_all();
+    for handle in handles {
+        handle.join();
+    }
+    assert!(!QUEUE.notify_one());
+}
+
+#[test_case]
+fn sleeping_threads_let_lower_priorities_run() {
+    use crate::time::Instant;
+    use core::sync::atomic::AtomicBool;
+
+    static WAITED: AtomicBool = AtomicBool::new(false);
+
+    let sleeper = spawn_with_priority("test", Priority::High, || {
+        let start = Instant::now();
+        time::sleep(Duration::from_millis(20));
+        (start.elapsed(), WAITED.load(Ordering::Relaxed))
+    })
+    .unwrap();
+
+    // the sleeper runs first and would keep this thread from running if it stayed ready
+    yield_now();
+    WAITED.store(true, Ordering::Relaxed);
+
+    let (slept, waited) = sleeper.join();
+    assert!(waited);
+    assert!(slept >= Duration::from_millis(20));
+    assert!(slept < Duration::from_millis(200));
+}
@@ -0,0 +1,44 @@
+use klib::percpu;
+use x86_64::instructions::interrupts;
+
+use super::{scheduler::reschedule, scheduler::scheduler, ThreadId, ThreadState};
+
+/// Lets the blocking primitives of [`klib::sync`] put threads to sleep.
+pub(super) struct Parking;
+
+impl klib::sync::Scheduler for Parking {
+    fn current_thread(&self) -> u64 {
+        percpu::current()
+            .current_thread()
+            .expect("threads are not initialized")
+    }
+
+    fn park(&self) {
+        interrupts::without_interrupts(|| {
+            let mut scheduler = scheduler();
+            if scheduler.current().is_none() {
+                return;
+            }
+
+            let thread = scheduler.current_thread();
+            if thread.unpark_token {
+                thread.unpark_token = false;
+                return;
+            }
+
+            reschedule(scheduler, ThreadState::Blocked);
+        });
+    }
+
+    fn unpark(&self, thread: u64) {
+        let id = ThreadId(thread);
+        let mut scheduler = scheduler();
+
+        match scheduler.threads.get_mut(&id) {
+            Some(thread) if thread.state == ThreadState::Blocked => scheduler.wake(id),
+            // the thread did not park yet, so its next park returns immediately
+            Some(thread) => thread.unpark_token = true,
+            None => {}
+        }
+    }
+}
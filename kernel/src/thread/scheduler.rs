@@ -0,0 +1,309 @@
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    vec::Vec,
+};
+use klib::{
+    interrupts::{UninterruptibleMutex, UninterruptibleMutexGuard},
+    percpu,
+};
+use x86_64::instructions::interrupts;
+
+use super::{context, Priority, Thread, ThreadId, ThreadState};
+use crate::{memory, time};
+
+/// Number of timer ticks a thread runs before it is preempted.
+const TIME_SLICE: u64 = 10;
+
+static SCHEDULER: UninterruptibleMutex<Scheduler> = UninterruptibleMutex::new(Scheduler::new());
+
+/// Picks the ready thread with the highest priority, and takes turns between threads with the
+/// same priority. The idle thread of a CPU runs when no other thread is ready.
+pub(super) struct Scheduler {
+    /// Every thread that was not joined or detached after it exited. Threads are boxed so that
+    /// the location of their saved stack pointer does not move.
+    pub(super) threads: BTreeMap<ThreadId, Box<Thread>>,
+    /// A queue of ready threads for every priority.
+    ready: [VecDeque<ThreadId>; Priority::COUNT],
+    /// The state of every CPU, indexed by the CPU ID.
+    cpus: Vec<CpuState>,
+    /// Blocked threads the timer wakes, ordered by the nanosecond timestamp they wake at.
+    sleeping: BTreeSet<(u64, ThreadId)>,
+}
+
+/// The part of the scheduler every CPU has a copy of.
+#[derive(Debug, Default)]
+struct CpuState {
+    idle: Option<ThreadId>,
+    /// The thread the CPU switched away from, which [`finish_switch`] queues again or frees.
+    previous: Option<ThreadId>,
+    /// The timestamp from which the CPU time of the running thread is counted.
+    last_switch: u64,
+}
+
+/// What to do after the current thread stopped running.
+enum Next {
+    /// The current thread keeps running.
+    Continue,
+    Switch {
+        from: *mut u64,
+        to: u64,
+    },
+}
+
+pub(super) fn scheduler() -> UninterruptibleMutexGuard<'static, Scheduler> {
+    SCHEDULER.lock()
+}
+
+impl Scheduler {
+    const fn new() -> Self {
+        Self {
+            threads: BTreeMap::new(),
+            ready: [const { VecDeque::new() }; Priority::COUNT],
+            cpus: Vec::new(),
+            sleeping: BTreeSet::new(),
+        }
+    }
+
+    /// The state of the current CPU.
+    fn cpu(&mut self) -> &mut CpuState {
+        let id = percpu::current().id();
+        if id >= self.cpus.len() {
+            self.cpus.resize_with(id + 1, CpuState::default);
+        }
+        &mut self.cpus[id]
+    }
+
+    /// Makes `id`, which must be running on the current CPU, the thread the CPU runs when no
+    /// other thread is ready. It is never queued.
+    pub(super) fn set_idle(&mut self, id: ThreadId) {
+        self.cpu().idle = Some(id);
+    }
+
+    pub(super) fn add(&mut self, id: ThreadId, thread: Box<Thread>) {
+        let state = thread.state;
+        let priority = thread.priority;
+        self.threads.insert(id, thread);
+
+        if state == ThreadState::Ready {
+            self.make_ready(id, priority);
+        }
+    }
+
+    /// The running thread, or `None` before threads are initialized. It is part of the per-CPU
+    /// data, so that it can be read without locking the scheduler.
+    pub(super) fn current(&self) -> Option<ThreadId> {
+        percpu::current().current_thread().map(ThreadId)
+    }
+
+    pub(super) fn set_current(&mut self, id: ThreadId) {
+        percpu::current().set_current_thread(id.0);
+    }
+
+    pub(super) fn current_thread(&mut self) -> &mut Thread {
+        let id = self.current().expect("threads are not initialized");
+        self.threads.get_mut(&id).unwrap()
+    }
+
+    /// Makes a blocked thread runnable again.
+    pub(super) fn wake(&mut self, id: ThreadId) {
+        if let Some(thread) = self.threads.get_mut(&id) {
+            if thread.state == ThreadState::Blocked {
+                thread.state = ThreadState::Ready;
+                // a thread that is still switching away is queued by `finish_switch`, so that no
+                // CPU resumes it before its stack pointer is saved
+                if !thread.on_cpu {
+                    let priority = thread.priority;
+                    self.make_ready(id, priority);
+                }
+            }
+        }
+    }
+
+    /// Makes the timer wake thread `id` at the first tick at or after `deadline`.
+    pub(super) fn wake_at(&mut self, deadline: u64, id: ThreadId) {
+        self.sleeping.insert((deadline, id));
+    }
+
+    /// Stops the timer from waking thread `id` at `deadline`, if it was woken before.
+    pub(super) fn cancel_wake_at(&mut self, deadline: u64, id: ThreadId) {
+        self.sleeping.remove(&(deadline, id));
+    }
+
+    fn make_ready(&mut self, id: ThreadId, priority: Priority) {
+        self.ready[priority as usize].push_back(id);
+
+        // a more important thread does not wait for the time slice of the running one to end
+        let idle = self.cpu().idle;
+        let preempts_current = match self.current() {
+            Some(current) if Some(current) != idle => priority > self.threads[&current].priority,
+            _ => true,
+        };
+        if preempts_current {
+            percpu::current().request_reschedule();
+        }
+    }
+
+    /// Adds the CPU time since the last switch to the running thread.
+    pub(super) fn account_cpu_time(&mut self) {
+        let now = time::nanoseconds();
+        let cpu = self.cpu();
+        let elapsed = now.saturating_sub(cpu.last_switch);
+        cpu.last_switch = now;
+
+        if let Some(id) = self.current() {
+            self.threads.get_mut(&id).unwrap().cpu_time += elapsed;
+        }
+    }
+
+    /// Changes the state of the running thread to `state`, which keeps running until [`Self::next`]
+    /// picks another one. It is not queued before the switch away from it finished.
+    fn stop_current(&mut self, state: ThreadState) {
+        if let Some(id) = self.current() {
+            self.threads.get_mut(&id).unwrap().state = state;
+        }
+    }
+
+    /// Picks the next thread to run after [`Self::stop_current`]. A ready current thread keeps
+    /// running unless a queued thread has at least its priority.
+    fn next(&mut self) -> Next {
+        let Some(current_id) = self.current() else {
+            return Next::Continue;
+        };
+
+        let idle = self.cpu().idle;
+        let current = &self.threads[&current_id];
+        // the idle thread is only picked when no other thread is ready
+        let current_priority = (current.state == ThreadState::Ready && Some(current_id) != idle)
+            .then_some(current.priority as usize);
+        let queued = self.ready.iter().rposition(|queue| !queue.is_empty());
+
+        let next_id = match (queued, current_priority) {
+            (Some(priority), current_priority)
+                if current_priority.is_none_or(|current| priority >= current) =>
+            {
+                self.ready[priority].pop_front().unwrap()
+            }
+            (_, Some(_)) => current_id,
+            _ => idle.expect("no thread is ready and there is no idle thread"),
+        };
+
+        if next_id == current_id {
+            self.threads.get_mut(&current_id).unwrap().state = ThreadState::Running;
+            return Next::Continue;
+        }
+
+        self.account_cpu_time();
+        self.set_current(next_id);
+        percpu::current().count_context_switch();
+
+        self.cpu().previous = Some(current_id);
+        let from = &mut self.threads.get_mut(&current_id).unwrap().stack_pointer as *mut u64;
+
+        let next = self.threads.get_mut(&next_id).unwrap();
+        next.state = ThreadState::Running;
+        next.on_cpu = true;
+
+        if let Some(stack) = next.kernel_entry_stack {
+            percpu::current().set_kernel_stack(stack);
+        }
+        // the kernel is mapped the same way in every address space, so the switch can continue
+        // on the stack of the current thread
+        let level_4_frame = next.process.as_ref().map(|process| process.level_4_frame());
+        unsafe { memory::activate_level_4_table(level_4_frame) };
+
+        Next::Switch {
+            from,
+            to: next.stack_pointer,
+        }
+    }
+}
+
+/// Stops running the current thread, which changes to `state`, and runs the next ready thread.
+/// Returns when the current thread is scheduled again.
+///
+/// Interrupts must be disabled, so that nothing can run between releasing the scheduler and
+/// switching.
+pub(super) fn reschedule(mut scheduler: UninterruptibleMutexGuard<Scheduler>, state: ThreadState) {
+    debug_assert!(!interrupts::are_enabled());
+    scheduler.stop_current(state);
+
+    match scheduler.next() {
+        Next::Continue => {}
+        Next::Switch { from, to } => {
+            drop(scheduler);
+            percpu::current().start_time_slice(TIME_SLICE);
+
+            unsafe { context::switch(from, to) };
+
+            finish_switch();
+        }
+    }
+}
+
+/// Runs on the new thread after every switch, when the stack pointer of the previous thread is
+/// saved. Queues the previous thread again if it is ready, or frees the stack of an exited one,
+/// which could not be done while running on it, releases its process, whose address space was
+/// active until the switch, and wakes its joiner.
+pub(super) fn finish_switch() {
+    let (stack, process) = {
+        let mut scheduler = SCHEDULER.lock();
+        let cpu = scheduler.cpu();
+        let (Some(id), idle) = (cpu.previous.take(), cpu.idle) else {
+            return;
+        };
+
+        let thread = scheduler.threads.get_mut(&id).unwrap();
+        thread.on_cpu = false;
+
+        match thread.state {
+            ThreadState::Ready if Some(id) != idle => {
+                let priority = thread.priority;
+                scheduler.make_ready(id, priority);
+                return;
+            }
+            ThreadState::Exited => {}
+            _ => return,
+        }
+
+        let stack = thread.stack.take();
+        let process = thread.process.take();
+        let joiner = thread.joiner.take();
+        if thread.detached {
+            scheduler.threads.remove(&id);
+        }
+        if let Some(joiner) = joiner {
+            scheduler.wake(joiner);
+        }
+        (stack, process)
+    };
+
+    drop(stack);
+    drop(process);
+}
+
+/// Counts a timer tick against the time slice of the running thread and wakes the sleeping
+/// threads whose deadline passed.
+pub(crate) fn tick() {
+    percpu::current().count_tick();
+
+    let now = time::nanoseconds();
+    let mut scheduler = SCHEDULER.lock();
+    while let Some(&(deadline, id)) = scheduler.sleeping.first() {
+        if deadline > now {
+            break;
+        }
+
+        scheduler.sleeping.pop_first();
+        scheduler.wake(id);
+    }
+}
+
+/// Switches to another thread if the time slice of the running one expired or a more important
+/// thread became ready. Called at the end of interrupt handlers, after the end of the interrupt
+/// was signaled.
+pub(crate) fn preempt() {
+    if percpu::current().take_reschedule_request() {
+        reschedule(SCHEDULER.lock(), ThreadState::Ready);
+    }
+}
@@ -0,0 +1,152 @@
+use conquer_once::spin::OnceCell;
+use core::{fmt, time::Duration};
+
+use super::{
+    hpet::Hpet,
+    ticks, ticks_to_duration,
+    tsc::{self, Tsc},
+};
+use crate::acpi;
+
+static CLOCK_SOURCE: OnceCell<ClockSource> = OnceCell::uninit();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSourceKind {
+    /// The timer interrupt count, with a resolution of one tick.
+    Pit,
+    Hpet,
+    Tsc,
+}
+
+impl fmt::Display for ClockSourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pit => write!(f, "PIT"),
+            Self::Hpet => write!(f, "HPET"),
+            Self::Tsc => write!(f, "TSC"),
+        }
+    }
+}
+
+/// A counter that timestamps are read from. All clock sources continue the timeline of the timer
+/// interrupt count, so timestamps do not jump when the clock source is selected.
+pub enum ClockSource {
+    Pit,
+    Hpet {
+        hpet: Hpet,
+        /// The timestamp at which the HPET counter was started.
+        offset: u64,
+    },
+    Tsc(Tsc),
+}
+
+impl ClockSource {
+    pub fn kind(&self) -> ClockSourceKind {
+        match self {
+            Self::Pit => ClockSourceKind::Pit,
+            Self::Hpet { .. } => ClockSourceKind::Hpet,
+            Self::Tsc(_) => ClockSourceKind::Tsc,
+        }
+    }
+
+    pub fn nanoseconds(&self) -> u64 {
+        match self {
+            Self::Pit => ticks_to_duration(ticks()).as_nanos() as u64,
+            Self::Hpet { hpet, offset } => offset + hpet.nanoseconds(),
+            Self::Tsc(tsc) => tsc.nanoseconds(),
+        }
+    }
+}
+
+/// Selects the clock source for timestamps. The timer interrupt must be running.
+///
+/// Without a `preferred` clock source, the TSC is used if it is invariant, otherwise the HPET if
+/// there is one, otherwise the PIT.
+pub(super) fn init(preferred: Option<ClockSourceKind>) {
+    let hpet = acpi::tables()
+        .and_then(|tables| tables.hpet)
+        .and_then(|info| match unsafe { Hpet::new(&info) } {
+            Ok(hpet) => Some(ClockSource::Hpet {
+                hpet,
+                offset: ClockSource::Pit.nanoseconds(),
+            }),
+            Err(error) => {
+                log::warn!("HPET is unusable: {error}");
+                None
+            }
+        });
+
+    let use_tsc = match preferred {
+        Some(ClockSourceKind::Tsc) => {
+            if !tsc::is_invariant() {
+                log::warn!("The TSC is not invariant, timestamps may drift");
+            }
+            true
+        }
+        Some(_) => false,
+        None => tsc::is_invariant(),
+    };
+
+    let source = if use_tsc {
+        let reference = hpet.as_ref().unwrap_or(&ClockSource::Pit);
+        let tsc = Tsc::calibrate(reference);
+        log::info!(
+            "TSC runs at {} kHz, calibrated against the {}",
+            tsc.frequency() / 1000,
+            reference.kind()
+        );
+        ClockSource::Tsc(tsc)
+    } else {
+        match hpet {
+            Some(hpet) if preferred != Some(ClockSourceKind::Pit) => hpet,
+            _ => ClockSource::Pit,
+        }
+    };
+
+    if preferred.is_some_and(|preferred| preferred != source.kind()) {
+        log::warn!("Clock source {} is not available", preferred.unwrap());
+    }
+
+    log::info!("Clock source: {}", source.kind());
+    CLOCK_SOURCE.init_once(|| source);
+}
+
+/// Nanoseconds since the timer was started, read from the selected clock source.
+pub fn nanoseconds() -> u64 {
+    match CLOCK_SOURCE.get() {
+        Some(source) => source.nanoseconds(),
+        None => ClockSource::Pit.nanoseconds(),
+    }
+}
+
+/// A monotonic timestamp with nanosecond precision, like `std::time::Instant`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub fn now() -> Self {
+        Self(nanoseconds())
+    }
+
+    /// Returns the time passed since `earlier`, or zero if it is later than `self`.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.0.saturating_sub(earlier.0))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Self::now().duration_since(*self)
+    }
+}
+
+#[test_case]
+fn instants_measure_sleep() {
+    use super::sleep;
+
+    let start = Instant::now();
+    sleep(Duration::from_millis(10));
+    let elapsed = start.elapsed();
+
+    assert!(elapsed >= Duration::from_millis(9));
+    assert!(elapsed < Duration::from_millis(100));
+    assert!(Instant::now() > start);
+}
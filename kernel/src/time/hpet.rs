@@ -0,0 +1,84 @@
+use core::fmt;
+
+use crate::{
+    acpi,
+    memory::{ioremap, CacheMode, IoMem, VmmError},
+};
+
+// register offsets
+const CAPABILITIES: usize = 0x00;
+const CONFIGURATION: usize = 0x10;
+const MAIN_COUNTER: usize = 0xf0;
+const REGISTERS_SIZE: usize = 0x100;
+
+const CAPABILITY_64_BIT_COUNTER: u64 = 1 << 13;
+const CONFIGURATION_ENABLE: u64 = 1 << 0;
+
+const FEMTOSECONDS_PER_NANOSECOND: u64 = 1_000_000;
+/// The specification requires a period of at most 100 ns.
+const MAX_PERIOD: u64 = 100 * FEMTOSECONDS_PER_NANOSECOND;
+
+#[derive(Debug)]
+pub enum HpetError {
+    Map(VmmError),
+    /// A 32 bit counter overflows within minutes, which makes it useless as a clock source.
+    NarrowCounter,
+    InvalidPeriod(u64),
+}
+
+impl fmt::Display for HpetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Map(error) => write!(f, "failed to map the registers: {error}"),
+            Self::NarrowCounter => write!(f, "the counter is only 32 bits wide"),
+            Self::InvalidPeriod(period) => write!(f, "invalid counter period of {period} fs"),
+        }
+    }
+}
+
+/// The high precision event timer, used for its main counter. Its comparators are not used.
+pub struct Hpet {
+    registers: IoMem,
+    /// Femtoseconds per counter increment.
+    period: u64,
+}
+
+impl Hpet {
+    /// Maps the HPET described by ACPI, and restarts its main counter from 0.
+    ///
+    /// ## Safety
+    ///
+    /// The HPET must not be used by anything else.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn new(info: &acpi::Hpet) -> Result<Self, HpetError> {
+        let registers = unsafe { ioremap(info.base_address, REGISTERS_SIZE, CacheMode::Uncached) }
+            .map_err(HpetError::Map)?;
+
+        let capabilities: u64 = registers.read(CAPABILITIES);
+        if capabilities & CAPABILITY_64_BIT_COUNTER == 0 {
+            return Err(HpetError::NarrowCounter);
+        }
+
+        let period = capabilities >> 32;
+        if period == 0 || period > MAX_PERIOD {
+            return Err(HpetError::InvalidPeriod(period));
+        }
+
+        // the counter may only be written while it is halted
+        let configuration = registers.register::<u64>(CONFIGURATION);
+        configuration.update(|value| value & !CONFIGURATION_ENABLE);
+        registers.write(MAIN_COUNTER, 0u64);
+        configuration.update(|value| value | CONFIGURATION_ENABLE);
+
+        Ok(Self { registers, period })
+    }
+
+    pub fn counter(&self) -> u64 {
+        self.registers.read(MAIN_COUNTER)
+    }
+
+    /// Nanoseconds since the counter was started.
+    pub fn nanoseconds(&self) -> u64 {
+        (self.counter() as u128 * self.period as u128 / FEMTOSECONDS_PER_NANOSECOND as u128) as u64
+    }
+}
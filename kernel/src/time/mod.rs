@@ -0,0 +1,95 @@
+mod clock_source;
+mod hpet;
+mod pit;
+mod rtc;
+mod tsc;
+mod wall_clock;
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    acpi,
+    interrupts::{register_irq_handler, IrqMode, IrqReturn, TIMER_IRQ},
+    thread,
+};
+
+pub use clock_source::{nanoseconds, ClockSourceKind, Instant};
+pub use wall_clock::{DateTime, SystemTime};
+
+/// Number of timer interrupts per second.
+pub const TICK_FREQUENCY: u64 = 1000;
+
+const PIT_DIVISOR: u16 = pit::divisor(TICK_FREQUENCY);
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Starts the timer interrupt, selects the clock source and sets the wall clock from the RTC.
+pub(crate) fn init(clock_source: Option<ClockSourceKind>) {
+    register_irq_handler(
+        TIMER_IRQ,
+        "timer",
+        IrqMode::Exclusive,
+        timer_interrupt_handler,
+    )
+    .expect("failed to register the timer interrupt handler");
+
+    pit::start_periodic(PIT_DIVISOR);
+    clock_source::init(clock_source);
+
+    let century_register = acpi::tables()
+        .and_then(|tables| tables.fadt)
+        .and_then(|fadt| fadt.century_register);
+    match rtc::read(century_register) {
+        Some(now) => {
+            wall_clock::set(now.into());
+            log::info!("Wall clock set to {now} UTC");
+        }
+        None => {
+            wall_clock::set(SystemTime::UNIX_EPOCH);
+            log::warn!("The RTC holds no valid date, the wall clock starts at the Unix epoch");
+        }
+    }
+}
+
+fn timer_interrupt_handler() -> IrqReturn {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    thread::tick();
+    IrqReturn::Handled
+}
+
+/// Number of timer interrupts since the timer was started. It never decreases.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Time since the timer was started, with the resolution of one tick.
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+/// Blocks the current thread until at least `duration` has passed.
+pub fn sleep(duration: Duration) {
+    let deadline = nanoseconds().saturating_add(duration.as_nanos() as u64);
+    thread::sleep_until(deadline);
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    // the PIT does not divide its frequency evenly into milliseconds, so ticks are converted
+    // with the exact period
+    let nanoseconds =
+        ticks as u128 * PIT_DIVISOR as u128 * 1_000_000_000 / pit::PIT_FREQUENCY as u128;
+    Duration::from_nanos(nanoseconds as u64)
+}
+
+#[test_case]
+fn sleep_waits_for_the_duration() {
+    let start = uptime();
+    sleep(Duration::from_millis(20));
+    let slept = uptime() - start;
+
+    assert!(slept >= Duration::from_millis(20));
+    assert!(slept < Duration::from_millis(200));
+}
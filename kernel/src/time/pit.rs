@@ -0,0 +1,32 @@
+use x86_64::instructions::port::Port;
+
+/// Frequency of the oscillator driving the PIT.
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_0_DATA: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+/// Channel 0, low byte then high byte of the reload value, rate generator, binary counting.
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+/// Returns the reload value that makes the PIT fire closest to `frequency` times per second.
+pub const fn divisor(frequency: u64) -> u16 {
+    let divisor = (PIT_FREQUENCY + frequency / 2) / frequency;
+    assert!(
+        divisor > 1 && divisor <= u16::MAX as u64,
+        "unsupported PIT frequency"
+    );
+    divisor as u16
+}
+
+/// Makes channel 0 of the PIT raise IRQ 0 every `divisor` oscillations.
+pub fn start_periodic(divisor: u16) {
+    let mut command = Port::<u8>::new(COMMAND);
+    let mut data = Port::<u8>::new(CHANNEL_0_DATA);
+
+    unsafe {
+        command.write(CHANNEL_0_RATE_GENERATOR);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    }
+}
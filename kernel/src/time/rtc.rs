@@ -0,0 +1,210 @@
+use klib::interrupts::UninterruptibleMutex;
+use x86_64::instructions::port::Port;
+
+use super::DateTime;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+/// Set in the address written to the CMOS so that an NMI cannot interrupt the access.
+const NMI_DISABLE: u8 = 0x80;
+
+// CMOS register indices
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY_OF_MONTH: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+const STATUS_D: u8 = 0x0d;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// Set in the hours register for PM times in 12 hour mode.
+const HOUR_PM: u8 = 1 << 7;
+
+static CMOS: UninterruptibleMutex<Cmos> = UninterruptibleMutex::new(Cmos {
+    address: Port::new(CMOS_ADDRESS),
+    data: Port::new(CMOS_DATA),
+});
+
+struct Cmos {
+    address: Port<u8>,
+    data: Port<u8>,
+}
+
+/// The date and time registers as the RTC stores them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RawTime {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: Option<u8>,
+}
+
+impl Cmos {
+    fn read(&mut self, register: u8) -> u8 {
+        unsafe {
+            self.address.write(NMI_DISABLE | register);
+            self.data.read()
+        }
+    }
+
+    /// Enables NMIs again after the last read, leaving status register D selected as the
+    /// firmware does.
+    fn enable_nmi(&mut self) {
+        unsafe { self.address.write(STATUS_D) };
+    }
+
+    fn update_in_progress(&mut self) -> bool {
+        self.read(STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+    }
+
+    fn read_time(&mut self, century_register: Option<u8>) -> RawTime {
+        while self.update_in_progress() {}
+
+        RawTime {
+            seconds: self.read(SECONDS),
+            minutes: self.read(MINUTES),
+            hours: self.read(HOURS),
+            day: self.read(DAY_OF_MONTH),
+            month: self.read(MONTH),
+            year: self.read(YEAR),
+            century: century_register.map(|register| self.read(register)),
+        }
+    }
+}
+
+/// Reads the date and time from the RTC, which is assumed to keep UTC.
+///
+/// `century_register` is the CMOS index of the century, if the firmware provides one. Without it
+/// the year is assumed to be in the 21st century. Returns `None` if the RTC holds no valid date
+/// after the Unix epoch, for example because its battery is dead.
+pub fn read(century_register: Option<u8>) -> Option<DateTime> {
+    let mut cmos = CMOS.lock();
+
+    // the RTC may start an update between waiting for it and reading the registers, so they are
+    // read until two reads agree
+    let mut time = cmos.read_time(century_register);
+    loop {
+        let again = cmos.read_time(century_register);
+        if again == time {
+            break;
+        }
+        time = again;
+    }
+
+    let status_b = cmos.read(STATUS_B);
+    cmos.enable_nmi();
+
+    let time = decode(time, status_b);
+    time.is_valid().then_some(time)
+}
+
+fn decode(time: RawTime, status_b: u8) -> DateTime {
+    let binary = status_b & STATUS_B_BINARY != 0;
+    let value = |value: u8| {
+        if binary {
+            value
+        } else {
+            (value >> 4) * 10 + (value & 0x0f)
+        }
+    };
+
+    let mut hour = value(time.hours & !HOUR_PM);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is midnight and 12 PM is noon
+        hour %= 12;
+        if time.hours & HOUR_PM != 0 {
+            hour += 12;
+        }
+    }
+
+    let century = time.century.map_or(20, value) as u16;
+
+    DateTime {
+        year: century * 100 + value(time.year) as u16,
+        month: value(time.month),
+        day: value(time.day),
+        hour,
+        minute: value(time.minutes),
+        second: value(time.seconds),
+    }
+}
+
+#[test_case]
+fn rtc_formats_are_decoded() {
+    let time = RawTime {
+        seconds: 0x59,
+        minutes: 0x30,
+        hours: HOUR_PM | 0x12,
+        day: 0x29,
+        month: 0x02,
+        year: 0x24,
+        century: None,
+    };
+    let expected = DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 12,
+        minute: 30,
+        second: 59,
+    };
+    assert_eq!(decode(time, 0), expected);
+
+    let midnight = RawTime {
+        hours: 0x12,
+        ..time
+    };
+    assert_eq!(decode(midnight, 0).hour, 0);
+
+    let binary = RawTime {
+        seconds: 59,
+        minutes: 30,
+        hours: 12,
+        day: 29,
+        month: 2,
+        year: 24,
+        century: Some(20),
+    };
+    assert_eq!(decode(binary, STATUS_B_BINARY | STATUS_B_24_HOUR), expected);
+}
+
+#[test_case]
+fn cleared_rtcs_are_invalid() {
+    let cleared = RawTime {
+        seconds: 0,
+        minutes: 0,
+        hours: 0,
+        day: 0,
+        month: 0,
+        year: 0,
+        century: Some(0x19),
+    };
+    assert!(!decode(cleared, 0).is_valid());
+    assert!(!decode(
+        RawTime {
+            day: 1,
+            month: 1,
+            ..cleared
+        },
+        0
+    )
+    .is_valid());
+    assert!(decode(
+        RawTime {
+            day: 1,
+            month: 1,
+            year: 0x70,
+            ..cleared
+        },
+        0
+    )
+    .is_valid());
+}
@@ -0,0 +1,75 @@
+use core::arch::x86_64::{__cpuid, _rdtsc};
+
+use super::clock_source::ClockSource;
+
+const CPUID_MAX_EXTENDED_LEAF: u32 = 0x8000_0000;
+const CPUID_ADVANCED_POWER_MANAGEMENT: u32 = 0x8000_0007;
+const CPUID_INVARIANT_TSC: u32 = 1 << 8;
+
+/// How long the TSC is compared against the reference clock.
+const CALIBRATION_TIME: u64 = 50_000_000;
+
+/// Whether the TSC runs at a constant rate in all power states, which makes it usable as a
+/// clock.
+pub fn is_invariant() -> bool {
+    __cpuid(CPUID_MAX_EXTENDED_LEAF).eax >= CPUID_ADVANCED_POWER_MANAGEMENT
+        && __cpuid(CPUID_ADVANCED_POWER_MANAGEMENT).edx & CPUID_INVARIANT_TSC != 0
+}
+
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// The time stamp counter, converted to nanoseconds with a frequency measured at boot.
+pub struct Tsc {
+    frequency: u64,
+    /// The TSC value and the timestamp of the reference clock at the same moment, so that the
+    /// TSC continues the timeline of the reference.
+    base: u64,
+    base_nanoseconds: u64,
+}
+
+impl Tsc {
+    /// Measures the TSC frequency against `reference`.
+    pub fn calibrate(reference: &ClockSource) -> Self {
+        // both measurements are taken right after the reference clock advanced, so that its
+        // resolution does not matter
+        let start = wait_for_change(reference);
+        let start_tsc = read();
+
+        let mut end = start;
+        while end - start < CALIBRATION_TIME {
+            end = reference.nanoseconds();
+        }
+        let end_tsc = read();
+
+        let frequency =
+            ((end_tsc - start_tsc) as u128 * 1_000_000_000 / (end - start) as u128) as u64;
+
+        Self {
+            frequency,
+            base: end_tsc,
+            base_nanoseconds: end,
+        }
+    }
+
+    /// Measured TSC increments per second.
+    pub fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    pub fn nanoseconds(&self) -> u64 {
+        let elapsed = read().saturating_sub(self.base);
+        self.base_nanoseconds + (elapsed as u128 * 1_000_000_000 / self.frequency as u128) as u64
+    }
+}
+
+fn wait_for_change(clock: &ClockSource) -> u64 {
+    let start = clock.nanoseconds();
+    loop {
+        let now = clock.nanoseconds();
+        if now != start {
+            return now;
+        }
+    }
+}
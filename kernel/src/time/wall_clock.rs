@@ -0,0 +1,176 @@
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use super::nanoseconds;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Nanoseconds between the Unix epoch and the start of the timer.
+static BOOT_TIME: AtomicU64 = AtomicU64::new(0);
+
+/// A point in wall clock time, like `std::time::SystemTime`.
+///
+/// Until the clock is set from the RTC, it counts from the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime {
+    since_epoch: Duration,
+}
+
+impl SystemTime {
+    /// 1970-01-01 00:00:00 UTC.
+    pub const UNIX_EPOCH: SystemTime = SystemTime {
+        since_epoch: Duration::ZERO,
+    };
+
+    pub fn now() -> Self {
+        Self {
+            since_epoch: Duration::from_nanos(BOOT_TIME.load(Ordering::Relaxed) + nanoseconds()),
+        }
+    }
+
+    /// Returns the time passed since `earlier`, or `None` if it is later than `self`.
+    pub fn duration_since(&self, earlier: SystemTime) -> Option<Duration> {
+        self.since_epoch.checked_sub(earlier.since_epoch)
+    }
+
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_unix_seconds(self.since_epoch.as_secs())
+    }
+}
+
+impl From<DateTime> for SystemTime {
+    fn from(date_time: DateTime) -> Self {
+        Self {
+            since_epoch: Duration::from_secs(date_time.unix_seconds()),
+        }
+    }
+}
+
+impl fmt::Display for SystemTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:03}",
+            self.date_time(),
+            self.since_epoch.subsec_millis()
+        )
+    }
+}
+
+/// Sets the wall clock so that `SystemTime::now` returns `now`.
+pub(super) fn set(now: SystemTime) {
+    let boot_time = now
+        .since_epoch
+        .saturating_sub(Duration::from_nanos(nanoseconds()));
+    BOOT_TIME.store(boot_time.as_nanos() as u64, Ordering::Relaxed);
+}
+
+/// A calendar date and time of day in UTC. Years before 1970 are not supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12.
+    pub month: u8,
+    /// 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn from_unix_seconds(seconds: u64) -> Self {
+        let days = seconds / SECONDS_PER_DAY;
+        let seconds = seconds % SECONDS_PER_DAY;
+
+        // the inverse of `unix_seconds`
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = era * 400 + year_of_era + (month <= 2) as u64;
+
+        Self {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+
+    /// Whether every field is in range and the date is not before the Unix epoch, which
+    /// [`Self::unix_seconds`] requires.
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// The seconds since the Unix epoch. The date must be valid, see [`Self::is_valid`].
+    pub fn unix_seconds(&self) -> u64 {
+        // counts days in eras of 400 years starting on March 1st, so that the leap day is the
+        // last day of a year
+        let month = self.month as u64;
+        let year = self.year as u64 - (month <= 2) as u64;
+        let era = year / 400;
+        let year_of_era = year % 400;
+        let shifted_month = (month + 9) % 12;
+        let day_of_year = (153 * shifted_month + 2) / 5 + self.day as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        days * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[test_case]
+fn dates_are_converted_to_unix_time() {
+    let leap_day = DateTime {
+        year: 2024,
+        month: 2,
+        day: 29,
+        hour: 23,
+        minute: 59,
+        second: 59,
+    };
+    assert_eq!(leap_day.unix_seconds(), 1_709_251_199);
+    assert_eq!(DateTime::from_unix_seconds(1_709_251_199), leap_day);
+
+    let epoch = DateTime::from_unix_seconds(0);
+    assert_eq!(epoch.unix_seconds(), 0);
+    assert_eq!(alloc::format!("{epoch}"), "1970-01-01 00:00:00");
+
+    let new_years_eve = DateTime::from_unix_seconds(946_598_400);
+    assert_eq!((new_years_eve.year, new_years_eve.month), (1999, 12));
+    assert_eq!(new_years_eve.day, 31);
+}
@@ -0,0 +1,98 @@
+use core::arch::naked_asm;
+use x86_64::{registers::rflags::RFlags, VirtAddr};
+
+use crate::thread;
+
+/// The flags user mode starts with. Bit 1 is reserved and always set.
+const USER_FLAGS: u64 = RFlags::INTERRUPT_FLAG.bits() | 0b10;
+
+/// Saves the callee-saved registers of the kernel and continues in user mode at `entry`, with
+/// the stack pointer `user_stack` and all other registers cleared. The saved registers are
+/// directly above the kernel entry stack of the thread.
+///
+/// Returns the status passed to [`return_to_kernel`].
+///
+/// ## Safety
+///
+/// The segments must be the user segments of the GDT, and `entry` and `user_stack` must be
+/// accessible from user mode. Interrupts are disabled until user mode runs.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn enter_user_mode(
+    entry: u64,
+    user_stack: u64,
+    code_segment: u64,
+    stack_segment: u64,
+) -> u64 {
+    naked_asm!(
+        "push rbp",
+        "push rbx",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        // interrupts and system calls start on a 16 byte aligned stack below the saved registers
+        "sub rsp, 8",
+        "mov r12, rdi",
+        "mov r13, rsi",
+        "mov r14, rdx",
+        "mov r15, rcx",
+        // the GS base of user mode must not be active while an interrupt can run in the kernel,
+        // so interrupts stay disabled until `iretq` loads the flags of user mode
+        "cli",
+        "mov rdi, rsp",
+        "call {entered}",
+        "push r15",
+        "push r13",
+        "push {flags}",
+        "push r14",
+        "push r12",
+        "swapgs",
+        // nothing of the kernel may leak to user mode through the registers
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "iretq",
+        entered = sym entered,
+        flags = const USER_FLAGS,
+    )
+}
+
+/// Records the kernel entry stack of the thread that is about to enter user mode.
+extern "C" fn entered(kernel_entry_stack: u64) {
+    thread::set_kernel_entry_stack(Some(VirtAddr::new(kernel_entry_stack)));
+}
+
+/// Makes the [`enter_user_mode`] call that saved its registers above `kernel_entry_stack`
+/// return `status`.
+///
+/// ## Safety
+///
+/// `kernel_entry_stack` must be the kernel entry stack of the current thread. Everything below
+/// it on the stack is discarded.
+#[unsafe(naked)]
+pub(super) unsafe extern "C" fn return_to_kernel(kernel_entry_stack: u64, status: u64) -> ! {
+    naked_asm!(
+        "mov rsp, rdi",
+        "mov rax, rsi",
+        "add rsp, 8",
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop rbx",
+        "pop rbp",
+        "ret",
+    )
+}
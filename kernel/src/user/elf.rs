@@ -0,0 +1,367 @@
+use alloc::{vec, vec::Vec};
+use core::{fmt, ops::Range};
+use x86_64::{instructions::random::RdRand, structures::paging::PageTableFlags, VirtAddr};
+
+use crate::{
+    memory::{AddressSpace, VmmError, USER_SIZE, USER_START},
+    time,
+};
+
+const MAGIC: &[u8] = b"\x7fELF";
+const CLASS_64: u8 = 2;
+const LITTLE_ENDIAN: u8 = 1;
+const CURRENT_VERSION: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 62;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+// types of the entries of the auxiliary vector
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+
+/// Size of the stack of a program, at the end of user space.
+pub const STACK_SIZE: u64 = 128 * 1024;
+
+/// How much of the stack the arguments, environment and auxiliary vector may take.
+const MAX_STACK_INFO_SIZE: u64 = STACK_SIZE / 4;
+
+#[derive(Debug)]
+pub enum ElfError {
+    NotElf,
+    /// The image ends before a table or segment its headers describe.
+    Truncated,
+    /// The image is not a 64 bit little endian x86_64 executable.
+    Unsupported(&'static str),
+    /// The loadable segment at the address is malformed, outside of user space or overlaps
+    /// another one.
+    BadSegment(u64),
+    /// The entry point is not inside an executable segment.
+    BadEntry(u64),
+    /// The arguments and environment do not fit on the stack.
+    ArgumentsTooLarge,
+    Memory(VmmError),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotElf => write!(f, "not an ELF image"),
+            Self::Truncated => write!(f, "the image is truncated"),
+            Self::Unsupported(reason) => write!(f, "unsupported image: {reason}"),
+            Self::BadSegment(address) => write!(f, "invalid segment at {address:#x}"),
+            Self::BadEntry(address) => write!(f, "entry point {address:#x} is not executable"),
+            Self::ArgumentsTooLarge => write!(f, "the arguments do not fit on the stack"),
+            Self::Memory(error) => write!(f, "failed to map the program: {error}"),
+        }
+    }
+}
+
+/// Where a program loaded into an address space starts running.
+#[derive(Debug, Clone, Copy)]
+pub struct Program {
+    pub entry: VirtAddr,
+    /// Points to the argument count, at the top of the stack.
+    pub stack_pointer: VirtAddr,
+}
+
+/// The fields of the ELF header the loader uses.
+struct Header {
+    entry: u64,
+    program_header_offset: u64,
+    program_header_count: u16,
+}
+
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    virtual_address: u64,
+    file_size: u64,
+    memory_size: u64,
+}
+
+impl Program {
+    /// Loads the statically linked ELF executable `image` into `address_space`, which should be
+    /// empty, with a stack that holds `arguments`, `environment` and the auxiliary vector the way
+    /// the System V ABI describes for the start of a process.
+    ///
+    /// On errors, the address space may be left with a part of the program.
+    pub fn load(
+        address_space: &mut AddressSpace,
+        image: &[u8],
+        arguments: &[&str],
+        environment: &[&str],
+    ) -> Result<Self, ElfError> {
+        let header = Header::parse(image)?;
+        let program_headers = program_header_table(image, &header)?;
+
+        let mut program_header_address = None;
+        let mut entry_is_executable = false;
+
+        for segment in program_headers.as_chunks::<PROGRAM_HEADER_SIZE>().0 {
+            let segment = ProgramHeader::parse(segment);
+            if segment.kind != PT_LOAD || segment.memory_size == 0 {
+                continue;
+            }
+
+            load_segment(address_space, image, &segment)?;
+
+            if segment.flags & PF_X != 0 && segment.memory_range().contains(&header.entry) {
+                entry_is_executable = true;
+            }
+            // the program can find its headers if they are part of a segment
+            let table_start = header.program_header_offset;
+            let table_end = table_start + program_headers.len() as u64;
+            if segment.offset <= table_start && table_end <= segment.offset + segment.file_size {
+                program_header_address =
+                    Some(segment.virtual_address + table_start - segment.offset);
+            }
+        }
+
+        if !entry_is_executable {
+            return Err(ElfError::BadEntry(header.entry));
+        }
+
+        let mut auxiliary_vector = vec![
+            (AT_PHENT, PROGRAM_HEADER_SIZE as u64),
+            (AT_PHNUM, u64::from(header.program_header_count)),
+            (AT_PAGESZ, 4096),
+            (AT_ENTRY, header.entry),
+        ];
+        if let Some(address) = program_header_address {
+            auxiliary_vector.push((AT_PHDR, address));
+        }
+
+        let stack_pointer = set_up_stack(address_space, arguments, environment, &auxiliary_vector)?;
+
+        Ok(Self {
+            entry: VirtAddr::new(header.entry),
+            stack_pointer,
+        })
+    }
+}
+
+impl Header {
+    fn parse(image: &[u8]) -> Result<Self, ElfError> {
+        if !image.starts_with(MAGIC) {
+            return Err(ElfError::NotElf);
+        }
+        if image.len() < HEADER_SIZE {
+            return Err(ElfError::Truncated);
+        }
+
+        if image[4] != CLASS_64 {
+            return Err(ElfError::Unsupported("not a 64 bit image"));
+        }
+        if image[5] != LITTLE_ENDIAN {
+            return Err(ElfError::Unsupported("not little endian"));
+        }
+        if image[6] != CURRENT_VERSION || read_u32(image, 20) != u32::from(CURRENT_VERSION) {
+            return Err(ElfError::Unsupported("unknown ELF version"));
+        }
+        if read_u16(image, 16) != TYPE_EXECUTABLE {
+            return Err(ElfError::Unsupported("not an executable"));
+        }
+        if read_u16(image, 18) != MACHINE_X86_64 {
+            return Err(ElfError::Unsupported("not an x86_64 image"));
+        }
+        if usize::from(read_u16(image, 54)) != PROGRAM_HEADER_SIZE {
+            return Err(ElfError::Unsupported("unknown program header size"));
+        }
+
+        Ok(Self {
+            entry: read_u64(image, 24),
+            program_header_offset: read_u64(image, 32),
+            program_header_count: read_u16(image, 56),
+        })
+    }
+}
+
+impl ProgramHeader {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            kind: read_u32(bytes, 0),
+            flags: read_u32(bytes, 4),
+            offset: read_u64(bytes, 8),
+            virtual_address: read_u64(bytes, 16),
+            file_size: read_u64(bytes, 32),
+            memory_size: read_u64(bytes, 40),
+        }
+    }
+
+    fn memory_range(&self) -> Range<u64> {
+        self.virtual_address..self.virtual_address.saturating_add(self.memory_size)
+    }
+}
+
+fn program_header_table<'a>(image: &'a [u8], header: &Header) -> Result<&'a [u8], ElfError> {
+    let size = usize::from(header.program_header_count) * PROGRAM_HEADER_SIZE;
+    usize::try_from(header.program_header_offset)
+        .ok()
+        .and_then(|start| image.get(start..start.checked_add(size)?))
+        .ok_or(ElfError::Truncated)
+}
+
+/// Maps the memory of `segment` with the permissions of its flags, and copies the part that is
+/// in the file. The rest stays zeroed.
+fn load_segment(
+    address_space: &mut AddressSpace,
+    image: &[u8],
+    segment: &ProgramHeader,
+) -> Result<(), ElfError> {
+    let bad_segment = ElfError::BadSegment(segment.virtual_address);
+    if segment.file_size > segment.memory_size {
+        return Err(bad_segment);
+    }
+    let Ok(start) = VirtAddr::try_new(segment.virtual_address) else {
+        return Err(bad_segment);
+    };
+
+    let bytes = usize::try_from(segment.offset)
+        .ok()
+        .zip(usize::try_from(segment.file_size).ok())
+        .and_then(|(offset, size)| image.get(offset..offset.checked_add(size)?))
+        .ok_or(ElfError::Truncated)?;
+
+    // pages are always readable
+    let mut flags = PageTableFlags::empty();
+    if segment.flags & PF_W != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if segment.flags & PF_X == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    address_space
+        .map(start, segment.memory_size, flags)
+        .map_err(|error| match error {
+            VmmError::NotUserAddress(_) | VmmError::Overlapping(_) => bad_segment,
+            error => ElfError::Memory(error),
+        })?;
+    address_space.write(start, bytes).map_err(ElfError::Memory)
+}
+
+/// Maps the stack at the end of user space and puts the argument count, the pointers to the
+/// arguments and the environment and the auxiliary vector at its 16 byte aligned top, followed
+/// by the strings. Returns the initial stack pointer, which points to the argument count.
+fn set_up_stack(
+    address_space: &mut AddressSpace,
+    arguments: &[&str],
+    environment: &[&str],
+    auxiliary_vector: &[(u64, u64)],
+) -> Result<VirtAddr, ElfError> {
+    let mut strings = Vec::new();
+    let mut string_offsets = Vec::new();
+    for string in arguments.iter().chain(environment) {
+        string_offsets.push(strings.len() as u64);
+        strings.extend_from_slice(string.as_bytes());
+        strings.push(0);
+    }
+    let random_offset = strings.len() as u64;
+    strings.extend_from_slice(&random_bytes());
+
+    // argc, the null terminated pointer arrays and the auxiliary vector with AT_RANDOM and AT_NULL
+    let word_count =
+        1 + (arguments.len() + 1) + (environment.len() + 1) + 2 * (auxiliary_vector.len() + 2);
+    let size = strings.len() as u64 + 8 * word_count as u64;
+    if size > MAX_STACK_INFO_SIZE {
+        return Err(ElfError::ArgumentsTooLarge);
+    }
+
+    let stack_top = USER_START + USER_SIZE;
+    let strings_start = (stack_top - strings.len() as u64).align_down(16u64);
+    let stack_pointer = (strings_start - 8 * word_count as u64).align_down(16u64);
+    let mut string_addresses = string_offsets
+        .iter()
+        .map(|offset| strings_start.as_u64() + offset);
+
+    let mut words = Vec::with_capacity(word_count);
+    words.push(arguments.len() as u64);
+    words.extend(string_addresses.by_ref().take(arguments.len()));
+    words.push(0);
+    words.extend(string_addresses);
+    words.push(0);
+    let random = (AT_RANDOM, strings_start.as_u64() + random_offset);
+    for &(kind, value) in auxiliary_vector.iter().chain(&[random, (AT_NULL, 0)]) {
+        words.extend([kind, value]);
+    }
+
+    let words: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+    address_space
+        .map(stack_top - STACK_SIZE, STACK_SIZE, flags)
+        .and_then(|()| address_space.write(stack_pointer, &words))
+        .and_then(|()| address_space.write(strings_start, &strings))
+        .map_err(ElfError::Memory)?;
+
+    Ok(stack_pointer)
+}
+
+/// Bytes for the program to seed the stack protector and random number generators with.
+fn random_bytes() -> [u8; 16] {
+    let rdrand = RdRand::new();
+    let mut bytes = [0; 16];
+
+    for chunk in bytes.as_chunks_mut::<8>().0 {
+        let value = rdrand
+            .and_then(RdRand::get_u64)
+            .unwrap_or_else(time::nanoseconds);
+        *chunk = value.to_le_bytes();
+    }
+
+    bytes
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+#[test_case]
+fn invalid_images_are_rejected() {
+    use super::HELLO;
+
+    let load = |image: &[u8], arguments: &[&str]| {
+        let mut address_space = AddressSpace::new().unwrap();
+        Program::load(&mut address_space, image, arguments, &[])
+    };
+
+    assert!(matches!(load(b"#!/bin/sh\n", &[]), Err(ElfError::NotElf)));
+    assert!(matches!(
+        load(&HELLO[..HEADER_SIZE - 1], &[]),
+        Err(ElfError::Truncated)
+    ));
+
+    let mut image = HELLO.to_vec();
+    image[18] = 3;
+    assert!(matches!(load(&image, &[]), Err(ElfError::Unsupported(_))));
+
+    let mut image = HELLO.to_vec();
+    image[24..32].copy_from_slice(&USER_START.as_u64().to_le_bytes());
+    assert!(matches!(load(&image, &[]), Err(ElfError::BadEntry(_))));
+
+    let arguments = ["x"; 4096];
+    assert!(matches!(
+        load(HELLO, &arguments),
+        Err(ElfError::ArgumentsTooLarge)
+    ));
+}
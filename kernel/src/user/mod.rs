@@ -0,0 +1,169 @@
+mod context;
+mod elf;
+
+use x86_64::{instructions::interrupts, VirtAddr};
+
+use crate::{gdt, thread};
+
+pub use elf::{ElfError, Program};
+
+/// The program in `programs/hello`, which is built separately and embedded in the kernel.
+pub static HELLO: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_HELLO"));
+
+/// How a program running in user mode ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The program exited with the code through a system call.
+    Exited(i32),
+    /// The program caused an exception and was killed.
+    Killed,
+}
+
+impl ExitStatus {
+    /// Encodes the status in a register. Exit codes only use the lower half.
+    fn into_raw(self) -> u64 {
+        match self {
+            Self::Exited(code) => code as u32 as u64,
+            Self::Killed => u64::MAX,
+        }
+    }
+
+    fn from_raw(raw: u64) -> Self {
+        match raw {
+            u64::MAX => Self::Killed,
+            code => Self::Exited(code as u32 as i32),
+        }
+    }
+}
+
+/// Continues the current thread in user mode at `entry`, with the stack pointer set to
+/// `stack_pointer` and all other registers cleared, until the program exits or is killed.
+///
+/// Interrupts and system calls from user mode run on the kernel stack of the thread, below the
+/// frame of this function.
+///
+/// ## Safety
+///
+/// The code at `entry` and the stack must be mapped in the current address space and accessible
+/// from user mode.
+#[deny(unsafe_op_in_unsafe_fn)]
+pub unsafe fn enter(entry: VirtAddr, stack_pointer: VirtAddr) -> ExitStatus {
+    let selectors = gdt::selectors();
+    let interrupts_enabled = interrupts::are_enabled();
+
+    let status = unsafe {
+        context::enter_user_mode(
+            entry.as_u64(),
+            stack_pointer.as_u64(),
+            u64::from(selectors.user_code_selector.0),
+            u64::from(selectors.user_data_selector.0),
+        )
+    };
+
+    thread::set_kernel_entry_stack(None);
+    if interrupts_enabled {
+        interrupts::enable();
+    }
+
+    ExitStatus::from_raw(status)
+}
+
+/// Ends the program the current thread runs in user mode, so that [`enter`] returns `status`.
+///
+/// ## Safety
+///
+/// Must be called by the handler of an interrupt, exception or system call that arrived from
+/// user mode. Everything on the stack since the thread entered the kernel is discarded without
+/// being dropped, so no locks may be held.
+#[deny(unsafe_op_in_unsafe_fn)]
+pub(crate) unsafe fn exit(status: ExitStatus) -> ! {
+    interrupts::disable();
+
+    let stack = thread::kernel_entry_stack().expect("the thread does not run in user mode");
+    unsafe { context::return_to_kernel(stack.as_u64(), status.into_raw()) }
+}
+
+#[test_case]
+fn faults_in_user_mode_kill_the_program() {
+    use crate::{
+        memory::{AddressSpace, USER_START},
+        process::Process,
+    };
+    use x86_64::structures::paging::{PageSize, PageTableFlags, Size4KiB};
+
+    let code = USER_START;
+    let stack_top = code + 2 * Size4KiB::SIZE;
+
+    let programs: [(&[u8], VirtAddr); 3] = [
+        // hlt is privileged and raises a general protection fault
+        (&[0xf4], stack_top),
+        // ud2 raises an invalid opcode exception
+        (&[0x0f, 0x0b], stack_top),
+        // push rax page faults below a stack that is not mapped
+        (&[0x50], stack_top + Size4KiB::SIZE),
+    ];
+
+    for (program, stack_pointer) in programs {
+        let mut address_space = AddressSpace::new().unwrap();
+        address_space
+            .map(code, 2 * Size4KiB::SIZE, PageTableFlags::WRITABLE)
+            .unwrap();
+        address_space.write(code, program).unwrap();
+
+        let handle = Process::new("user test", address_space)
+            .spawn_thread(code, stack_pointer)
+            .unwrap();
+        assert_eq!(handle.join(), ExitStatus::Killed);
+    }
+}
+
+#[test_case]
+fn page_faults_map_pages_on_demand() {
+    use crate::{
+        memory::{AddressSpace, USER_START},
+        process::Process,
+        syscall::EXIT,
+    };
+    use alloc::vec::Vec;
+    use x86_64::structures::paging::{PageSize, PageTableFlags, Size4KiB};
+
+    let code = USER_START;
+    let data = USER_START + 16 * Size4KiB::SIZE;
+
+    // mov rax, target; mov qword [rax], 42; mov rdi, [rax]; mov eax, EXIT; syscall
+    let program = |target: VirtAddr| -> Vec<u8> {
+        [0x48, 0xb8]
+            .into_iter()
+            .chain(target.as_u64().to_le_bytes())
+            .chain([0x48, 0xc7, 0x00, 42, 0x00, 0x00, 0x00])
+            .chain([0x48, 0x8b, 0x38, 0xb8])
+            .chain((EXIT as u32).to_le_bytes())
+            .chain([0x0f, 0x05])
+            .collect()
+    };
+
+    // the first access to the data region maps it, the code region cannot be written
+    let runs = [
+        (data + 8u64, ExitStatus::Exited(42)),
+        (code + Size4KiB::SIZE, ExitStatus::Killed),
+    ];
+    for (target, status) in runs {
+        let mut address_space = AddressSpace::new().unwrap();
+        address_space
+            .map(code, 2 * Size4KiB::SIZE, PageTableFlags::empty())
+            .unwrap();
+        address_space
+            .map(
+                data,
+                Size4KiB::SIZE,
+                PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            )
+            .unwrap();
+        address_space.write(code, &program(target)).unwrap();
+
+        let handle = Process::new("user test", address_space)
+            .spawn_thread(code, data + Size4KiB::SIZE)
+            .unwrap();
+        assert_eq!(handle.join(), status);
+    }
+}
@@ -1,5 +1,9 @@
 #![no_std]
 #![feature(decl_macro)]
 
+extern crate alloc;
+
 pub mod interrupts;
 pub mod io;
+pub mod percpu;
+pub mod sync;
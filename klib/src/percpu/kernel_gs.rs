@@ -0,0 +1,62 @@
+use x86_64::{instructions::segmentation::GS, registers::model_specific::GsBase};
+
+/// Privilege level of user mode, in the lowest bits of its code segment selector.
+const USER_PRIVILEGE_LEVEL: u64 = 3;
+
+/// Start of the higher half of the address space, where the kernel and its per-CPU data are.
+const HIGHER_HALF_START: u64 = 0xffff_8000_0000_0000;
+
+/// Makes the GS base refer to the per-CPU data while an interrupt or exception that arrived in
+/// user mode is handled, and restores the GS base of user mode when dropped.
+///
+/// The kernel keeps the GS base of user mode in the kernel GS base while it runs, and `swapgs`
+/// exchanges both when entering or leaving user mode.
+pub struct KernelGs {
+    swapped: bool,
+}
+
+impl KernelGs {
+    /// Swaps the GS base if the interrupted code ran in user mode.
+    ///
+    /// ## Safety
+    ///
+    /// `code_segment` must be the code segment saved in the stack frame of the running handler.
+    /// The guard must be created before the handler uses [`super::current`] and dropped right
+    /// before it returns, unless the handler never returns to the interrupted code.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn enter(code_segment: u64) -> Self {
+        let swapped = code_segment & 0b11 == USER_PRIVILEGE_LEVEL;
+        if swapped {
+            unsafe { GS::swap() };
+        }
+
+        Self { swapped }
+    }
+
+    /// Swaps the GS base if it is the one of user mode, for handlers like the NMI handler that can
+    /// interrupt the kernel right after it entered from user mode or before it returns there,
+    /// while the code segment is already the one of the kernel but the GS base is not.
+    ///
+    /// ## Safety
+    ///
+    /// The guard must be created before the handler uses [`super::current`] and dropped right
+    /// before it returns, unless the handler never returns to the interrupted code.
+    #[deny(unsafe_op_in_unsafe_fn)]
+    pub unsafe fn ensure() -> Self {
+        // user mode cannot set its GS base to the higher half, where the per-CPU data is
+        let swapped = GsBase::read().as_u64() < HIGHER_HALF_START;
+        if swapped {
+            unsafe { GS::swap() };
+        }
+
+        Self { swapped }
+    }
+}
+
+impl Drop for KernelGs {
+    fn drop(&mut self) {
+        if self.swapped {
+            unsafe { GS::swap() };
+        }
+    }
+}
@@ -0,0 +1,5 @@
+mod kernel_gs;
+mod per_cpu;
+
+pub use kernel_gs::*;
+pub use per_cpu::*;
@@ -0,0 +1,166 @@
+use core::{
+    mem, ptr,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering},
+};
+use x86_64::{registers::model_specific::GsBase, structures::tss::TaskStateSegment, VirtAddr};
+
+/// Marks that no thread runs on the CPU yet.
+const NO_THREAD: u64 = u64::MAX;
+
+/// The data every CPU has a copy of. A CPU finds its own through its GS base, which
+/// [`PerCpu::install`] points to it.
+///
+/// Only the owning CPU changes it, but other CPUs may read it.
+#[repr(C)]
+pub struct PerCpu {
+    id: usize,
+    current_thread: AtomicU64,
+    interrupts: AtomicU64,
+    context_switches: AtomicU64,
+    /// Timer ticks left until the running thread is preempted.
+    slice_remaining: AtomicU64,
+    /// Set when the running thread should be preempted at the end of the current interrupt.
+    need_reschedule: AtomicBool,
+    /// The TSS loaded on the CPU, or null before it is set.
+    tss: AtomicPtr<TaskStateSegment>,
+    /// The stack system calls switch to, which is also the privilege stack of the TSS.
+    kernel_stack: AtomicU64,
+    /// Holds the stack pointer of user mode while the system call entry switches stacks.
+    user_stack: AtomicU64,
+}
+
+/// Offset of the kernel stack of system calls in [`PerCpu`], for code that reaches it through
+/// the GS segment.
+pub const KERNEL_STACK_OFFSET: usize = mem::offset_of!(PerCpu, kernel_stack);
+/// Offset of the scratch space for the stack pointer of user mode in [`PerCpu`].
+pub const USER_STACK_OFFSET: usize = mem::offset_of!(PerCpu, user_stack);
+
+/// A snapshot of the counters of a CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuCounters {
+    /// Hardware interrupts handled by the CPU.
+    pub interrupts: u64,
+    pub context_switches: u64,
+}
+
+impl PerCpu {
+    /// Creates the data of the CPU with the kernel's number `id`.
+    pub const fn new(id: usize) -> Self {
+        Self {
+            id,
+            current_thread: AtomicU64::new(NO_THREAD),
+            interrupts: AtomicU64::new(0),
+            context_switches: AtomicU64::new(0),
+            slice_remaining: AtomicU64::new(0),
+            need_reschedule: AtomicBool::new(false),
+            tss: AtomicPtr::new(ptr::null_mut()),
+            kernel_stack: AtomicU64::new(0),
+            user_stack: AtomicU64::new(0),
+        }
+    }
+
+    /// Makes [`current`] return `self` on the current CPU. Every CPU needs data of its own.
+    pub fn install(&'static self) {
+        GsBase::write(VirtAddr::from_ptr(self));
+    }
+
+    /// The number the kernel gave the CPU. The BSP is CPU 0.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The ID of the thread running on the CPU, if the scheduler runs.
+    pub fn current_thread(&self) -> Option<u64> {
+        match self.current_thread.load(Ordering::Relaxed) {
+            NO_THREAD => None,
+            thread => Some(thread),
+        }
+    }
+
+    pub fn set_current_thread(&self, thread: u64) {
+        self.current_thread.store(thread, Ordering::Relaxed);
+    }
+
+    /// Remembers the TSS loaded on the CPU, so that [`PerCpu::set_kernel_stack`] can change it.
+    ///
+    /// ## Safety
+    ///
+    /// `tss` must be loaded on the CPU `self` belongs to and stay valid for as long as the CPU
+    /// runs. Nothing else may access it.
+    pub unsafe fn set_tss(&self, tss: *mut TaskStateSegment) {
+        self.tss.store(tss, Ordering::Relaxed);
+    }
+
+    /// Sets the stack the CPU switches to when an interrupt, exception or system call arrives in
+    /// user mode. Must be called on the CPU `self` belongs to.
+    ///
+    /// Panics if no TSS was set.
+    pub fn set_kernel_stack(&self, stack_top: VirtAddr) {
+        let tss = self.tss.load(Ordering::Relaxed);
+        assert!(!tss.is_null(), "the TSS of CPU {} is not set", self.id);
+
+        // only the owning CPU accesses the TSS, and it reads it only on ring transitions
+        unsafe { (*tss).privilege_stack_table[0] = stack_top };
+        self.kernel_stack
+            .store(stack_top.as_u64(), Ordering::Relaxed);
+    }
+
+    pub fn count_interrupt(&self) {
+        self.interrupts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_context_switch(&self) {
+        self.context_switches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Gives the thread that starts running `ticks` timer ticks before it is preempted.
+    pub fn start_time_slice(&self, ticks: u64) {
+        self.slice_remaining.store(ticks, Ordering::Relaxed);
+    }
+
+    /// Counts a timer tick against the time slice and requests a reschedule when it expired.
+    pub fn count_tick(&self) {
+        // only the timer interrupt handler of the CPU decrements it, so it cannot change in
+        // between
+        let remaining = self
+            .slice_remaining
+            .load(Ordering::Relaxed)
+            .saturating_sub(1);
+        self.slice_remaining.store(remaining, Ordering::Relaxed);
+
+        if remaining == 0 {
+            self.request_reschedule();
+        }
+    }
+
+    pub fn request_reschedule(&self) {
+        self.need_reschedule.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether a reschedule was requested since the last call.
+    pub fn take_reschedule_request(&self) -> bool {
+        self.need_reschedule.swap(false, Ordering::Relaxed)
+    }
+
+    pub fn counters(&self) -> CpuCounters {
+        CpuCounters {
+            interrupts: self.interrupts.load(Ordering::Relaxed),
+            context_switches: self.context_switches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Returns the data of the current CPU.
+///
+/// Panics if none was installed on the CPU.
+pub fn current() -> &'static PerCpu {
+    try_current().expect("per-CPU data is not installed")
+}
+
+/// Returns the data of the current CPU, or `None` if none was installed on it.
+pub fn try_current() -> Option<&'static PerCpu> {
+    let base = GsBase::read();
+
+    // only `install` sets the GS base, to a `PerCpu` that is never freed
+    (!base.is_null()).then(|| unsafe { &*base.as_ptr::<PerCpu>() })
+}
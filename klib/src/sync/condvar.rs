@@ -0,0 +1,61 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::{MutexGuard, WaitQueue};
+
+/// A condition variable for waiting on a [`Mutex`](super::Mutex) until another thread changes
+/// the data it protects. Wakeups may be spurious, so the condition has to be checked in a loop,
+/// or with [`Condvar::wait_while`].
+pub struct Condvar {
+    /// Counts notifications, so that a waiter notices one that happens after it unlocked the
+    /// mutex but before it slept.
+    notifications: AtomicU64,
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            notifications: AtomicU64::new(0),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Unlocks the mutex, waits for a notification and locks the mutex again.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let notifications = self.notifications.load(Ordering::Acquire);
+        drop(guard);
+
+        self.waiters
+            .wait_until(|| self.notifications.load(Ordering::Acquire) != notifications);
+        mutex.lock()
+    }
+
+    /// Waits until `condition` returns false for the data protected by the mutex.
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    pub fn notify_one(&self) {
+        self.notifications.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.notifications.fetch_add(1, Ordering::Release);
+        self.waiters.notify_all();
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
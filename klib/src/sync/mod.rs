@@ -0,0 +1,13 @@
+mod condvar;
+mod mutex;
+mod rwlock;
+mod scheduler;
+mod semaphore;
+mod wait_queue;
+
+pub use condvar::*;
+pub use mutex::*;
+pub use rwlock::*;
+pub use scheduler::*;
+pub use semaphore::*;
+pub use wait_queue::*;
@@ -0,0 +1,26 @@
+use conquer_once::{spin::OnceCell, TryInitError};
+
+/// The part of the kernel scheduler the blocking primitives need.
+///
+/// It has park and unpark semantics: unparking a thread that is not parked makes its next
+/// `park` return immediately, so a wakeup between deciding to wait and parking is not lost.
+pub trait Scheduler: Sync {
+    /// Identifies the current thread.
+    fn current_thread(&self) -> u64;
+
+    /// Blocks the current thread until it is unparked. It may also return spuriously.
+    fn park(&self);
+
+    fn unpark(&self, thread: u64);
+}
+
+static SCHEDULER: OnceCell<&'static dyn Scheduler> = OnceCell::uninit();
+
+/// Makes the blocking primitives put waiting threads to sleep. Until it is called, they spin.
+pub fn set_scheduler(scheduler: &'static dyn Scheduler) -> Result<(), TryInitError> {
+    SCHEDULER.try_init_once(|| scheduler)
+}
+
+pub(super) fn scheduler() -> Option<&'static dyn Scheduler> {
+    SCHEDULER.get().copied()
+}
@@ -0,0 +1,83 @@
+use alloc::collections::VecDeque;
+use core::hint;
+
+use super::scheduler;
+use crate::interrupts::UninterruptibleMutex;
+
+/// A queue of threads waiting for a condition.
+///
+/// Waiting must not happen in interrupt handlers. Before a scheduler is set, waiting spins.
+pub struct WaitQueue {
+    waiters: UninterruptibleMutex<VecDeque<u64>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: UninterruptibleMutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Blocks the current thread until `condition` returns true. The condition is checked again
+    /// every time the thread is woken.
+    pub fn wait_until(&self, mut condition: impl FnMut() -> bool) {
+        let Some(scheduler) = scheduler() else {
+            while !condition() {
+                hint::spin_loop();
+            }
+            return;
+        };
+
+        let thread = scheduler.current_thread();
+
+        while !condition() {
+            self.waiters.lock().push_back(thread);
+
+            // a notification between the first check and queueing would be missed
+            if condition() {
+                self.remove(thread);
+                return;
+            }
+
+            scheduler.park();
+            // the wakeup may be spurious, or for a notification that is stale by now
+            self.remove(thread);
+        }
+    }
+
+    /// Wakes the thread that waits the longest. Returns whether there was one.
+    pub fn notify_one(&self) -> bool {
+        let thread = self.waiters.lock().pop_front();
+
+        match (thread, scheduler()) {
+            (Some(thread), Some(scheduler)) => {
+                scheduler.unpark(thread);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Wakes all waiting threads and returns how many there were.
+    pub fn notify_all(&self) -> usize {
+        let threads = core::mem::take(&mut *self.waiters.lock());
+
+        if let Some(scheduler) = scheduler() {
+            for &thread in &threads {
+                scheduler.unpark(thread);
+            }
+        }
+
+        threads.len()
+    }
+
+    fn remove(&self, thread: u64) {
+        self.waiters.lock().retain(|&waiter| waiter != thread);
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
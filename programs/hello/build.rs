@@ -0,0 +1,4 @@
+fn main() {
+    // programs run in the address range of user mode, which starts at 64 TiB
+    println!("cargo:rustc-link-arg-bins=--image-base=0x400000000000");
+}
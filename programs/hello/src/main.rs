@@ -0,0 +1,128 @@
+//! A program for the ELF loader of the kernel. It prints its arguments and environment, checks
+//! the auxiliary vector and exits with `10 * argc + envc`. With the argument `write-text` or
+//! `run-rodata`, it violates the permissions of its segments and is killed instead.
+
+#![no_std]
+#![no_main]
+
+use core::{
+    arch::{asm, naked_asm},
+    ffi::CStr,
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const WRITE: u64 = 0;
+const EXIT: u64 = 1;
+
+const AT_NULL: usize = 0;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+
+/// `ret`, which is not executable in the read-only data.
+static RETURN: [u8; 1] = [0xc3];
+
+/// In the writable data, and in the zeroed data respectively.
+static WRITTEN: AtomicUsize = AtomicUsize::new(1);
+static ZEROED: AtomicUsize = AtomicUsize::new(0);
+
+/// The entry point. The stack pointer points to argc, followed by argv, envp and the auxiliary
+/// vector.
+#[unsafe(naked)]
+#[no_mangle]
+unsafe extern "C" fn _start() -> ! {
+    naked_asm!("mov rdi, rsp", "call {main}", "ud2", main = sym main)
+}
+
+extern "C" fn main(stack: *const usize) -> ! {
+    let argc = unsafe { *stack };
+    let argv = unsafe { stack.add(1) } as *const *const u8;
+    let envp = unsafe { argv.add(argc + 1) };
+
+    let mut envc = 0;
+    while !unsafe { *envp.add(envc) }.is_null() {
+        envc += 1;
+    }
+
+    for index in 0..argc + 1 + envc {
+        if index == argc {
+            continue;
+        }
+        let string = unsafe { CStr::from_ptr(*argv.add(index) as *const _) };
+        write(string.to_bytes());
+        write(b" ");
+    }
+    write(b"\n");
+
+    let mut auxv = unsafe { envp.add(envc + 1) } as *const [usize; 2];
+    let mut checks = 0;
+    loop {
+        let [kind, value] = unsafe { *auxv };
+        match kind {
+            AT_NULL => break,
+            AT_PAGESZ => check(&mut checks, value == 4096),
+            AT_ENTRY => check(&mut checks, value == _start as *const () as usize),
+            AT_RANDOM => check(&mut checks, value != 0),
+            _ => {}
+        }
+        auxv = unsafe { auxv.add(1) };
+    }
+    if checks != 3 {
+        exit(100 + checks);
+    }
+
+    WRITTEN.fetch_add(1, Ordering::Relaxed);
+    ZEROED.fetch_add(1, Ordering::Relaxed);
+    if WRITTEN.load(Ordering::Relaxed) != 2 || ZEROED.load(Ordering::Relaxed) != 1 {
+        exit(110);
+    }
+
+    if argc > 1 {
+        let argument = unsafe { CStr::from_ptr(*argv.add(1) as *const _) };
+        match argument.to_bytes() {
+            b"write-text" => unsafe { (_start as *const () as *mut u8).write_volatile(0xcc) },
+            b"run-rodata" => unsafe { asm!("call {}", in(reg) RETURN.as_ptr()) },
+            _ => {}
+        }
+    }
+
+    exit((10 * argc + envc) as i32);
+}
+
+fn check(passed: &mut i32, condition: bool) {
+    if condition {
+        *passed += 1;
+    }
+}
+
+fn write(bytes: &[u8]) {
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") WRITE => _,
+            in("rdi") bytes.as_ptr(),
+            in("rsi") bytes.len(),
+            lateout("rcx") _,
+            lateout("r11") _,
+            options(nostack),
+        )
+    };
+}
+
+fn exit(code: i32) -> ! {
+    unsafe {
+        asm!(
+            "syscall",
+            in("rax") EXIT,
+            in("rdi") code,
+            options(noreturn, nostack),
+        )
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    write(b"hello panicked\n");
+    exit(255);
+}
@@ -22,6 +22,7 @@ fn run_qemu(serial_output: bool, hide_window: bool) {
     qemu.arg("-drive")
         .arg(format!("format=raw,file={uefi_image}"));
     qemu.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
+    qemu.arg("-smp").arg("4");
 
     if hide_window {
         qemu.arg("-display").arg("none");